@@ -4,5 +4,5 @@ use vergen::{
 };
 
 fn main() {
-    vergen(Config::default()).unwrap()
+    vergen(Config::default()).unwrap();
 }
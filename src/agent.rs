@@ -0,0 +1,327 @@
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{
+    bail,
+    Context,
+    Error,
+};
+use chrono::Utc;
+use prometheus::proto::MetricFamily;
+use rand::Rng;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tokio::{
+    sync::mpsc::{
+        self,
+        error::TrySendError,
+    },
+    time::{
+        self,
+        MissedTickBehavior,
+    },
+};
+
+use crate::{
+    input::Entry,
+    output::{
+        self,
+        Write,
+    },
+    probe::AnyProbe,
+};
+
+/// How many flushes can wait for an output that is still busy writing
+/// before new flushes are dropped for it.
+const PENDING_FLUSHES: usize = 4;
+
+/// Settings of agent mode in which the inputs are collected on an interval
+/// and written to the outputs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Options {
+    /// How often inputs are collected if they have no interval of their own.
+    #[serde(with = "humantime_serde")]
+    pub(crate) interval: Duration,
+
+    /// Each collection is delayed by a random duration up to this long so
+    /// not every host of a fleet collects at the same moment.
+    #[serde(with = "humantime_serde")]
+    pub(crate) jitter: Duration,
+
+    /// How often the collected metrics are written to the outputs.
+    #[serde(with = "humantime_serde")]
+    pub(crate) flush_interval: Duration,
+
+    /// Each flush is delayed by a random duration up to this long.
+    #[serde(with = "humantime_serde")]
+    pub(crate) flush_jitter: Duration,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(10),
+            jitter: Duration::ZERO,
+            flush_interval: Duration::from_secs(10),
+            flush_jitter: Duration::ZERO,
+        }
+    }
+}
+
+/// Collects every input on its interval and writes the metrics to `outputs`
+/// on every flush. Only returns if an output can not be created or stopped.
+pub(crate) async fn run(
+    options: &Options,
+    inputs: &[Entry],
+    outputs: &[output::Entry],
+) -> Result<(), Error> {
+    // Every output writes in its own task so an output that is retrying does
+    // not hold up the other outputs or the collection of the inputs.
+    let senders = outputs
+        .iter()
+        .zip(build(outputs)?)
+        .map(|(entry, writer)| {
+            let (sender, receiver) = mpsc::channel(PENDING_FLUSHES);
+
+            tokio::spawn(write(
+                entry.output.name(),
+                writer,
+                options.flush_jitter,
+                receiver,
+            ));
+
+            (entry.output.name(), sender)
+        })
+        .collect::<Vec<_>>();
+
+    let (sender, mut receiver) = mpsc::channel(inputs.len().max(1));
+
+    for entry in inputs {
+        tokio::spawn(collect(
+            entry.probe(),
+            entry.interval.unwrap_or(options.interval),
+            options.jitter,
+            sender.clone(),
+        ));
+    }
+
+    drop(sender);
+
+    let mut flush = time::interval(options.flush_interval);
+    flush.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    // The first tick completes immediately and there is nothing to flush yet.
+    flush.tick().await;
+
+    let mut buffer = Vec::new();
+
+    loop {
+        tokio::select! {
+            Some(families) = receiver.recv() => merge(&mut buffer, families),
+
+            _ = flush.tick() => {
+                if buffer.is_empty() {
+                    continue;
+                }
+
+                let families = Arc::new(std::mem::take(&mut buffer));
+
+                for (name, sender) in &senders {
+                    match sender.try_send(Arc::clone(&families)) {
+                        Ok(()) => {}
+
+                        Err(TrySendError::Full(_)) => {
+                            eprintln!("output {name} is still writing, dropping flush");
+                        }
+
+                        Err(TrySendError::Closed(_)) => bail!("output {name} stopped"),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Collects every input once and writes the metrics to `outputs`. Fails if
+/// any of the outputs failed.
+pub(crate) async fn once(inputs: &[Entry], outputs: &[output::Entry]) -> Result<(), Error> {
+    let mut writers = build(outputs)?;
+
+    let handles = inputs
+        .iter()
+        .map(|entry| {
+            let probe = entry.probe();
+            tokio::spawn(async move { gather(probe.as_ref()).await })
+        })
+        .collect::<Vec<_>>();
+
+    let mut buffer = Vec::new();
+
+    for handle in handles {
+        merge(&mut buffer, handle.await?);
+    }
+
+    let mut failed = 0;
+
+    for (entry, writer) in outputs.iter().zip(&mut writers) {
+        if let Err(err) = writer.write(&buffer).await {
+            eprintln!("output {} failed: {err:#}", entry.output.name());
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        bail!("{failed} of {} outputs failed", outputs.len())
+    }
+
+    Ok(())
+}
+
+fn build(outputs: &[output::Entry]) -> Result<Vec<Box<dyn Write>>, Error> {
+    outputs
+        .iter()
+        .map(|entry| {
+            entry
+                .build()
+                .with_context(|| format!("can not create output {}", entry.output.name()))
+        })
+        .collect()
+}
+
+/// Writes every flush received from `receiver` to `writer` after a random
+/// delay up to `max_jitter`.
+async fn write(
+    name: &'static str,
+    mut writer: Box<dyn Write>,
+    max_jitter: Duration,
+    mut receiver: mpsc::Receiver<Arc<Vec<MetricFamily>>>,
+) {
+    while let Some(families) = receiver.recv().await {
+        time::sleep(jitter(max_jitter)).await;
+
+        if let Err(err) = writer.write(&families).await {
+            eprintln!("output {name} failed: {err:#}");
+        }
+    }
+}
+
+async fn collect(
+    probe: Arc<dyn AnyProbe>,
+    interval: Duration,
+    max_jitter: Duration,
+    sender: mpsc::Sender<Vec<MetricFamily>>,
+) {
+    let mut ticker = time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+        time::sleep(jitter(max_jitter)).await;
+
+        if sender.send(gather(probe.as_ref()).await).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Gathers the metrics of `probe` with the time of the collection as
+/// timestamp.
+async fn gather(probe: &dyn AnyProbe) -> Vec<MetricFamily> {
+    let timestamp = Utc::now().timestamp_millis();
+    let mut families = probe.gather().await;
+
+    for family in &mut families {
+        for metric in family.mut_metric().iter_mut() {
+            metric.set_timestamp_ms(timestamp);
+        }
+    }
+
+    families
+}
+
+fn jitter(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+
+    rand::thread_rng().gen_range(Duration::ZERO..max)
+}
+
+/// Adds `families` to `buffer` so every metric name appears only once even
+/// if an input was collected more than once between two flushes. Only the
+/// newest sample of every series is kept.
+fn merge(buffer: &mut Vec<MetricFamily>, families: Vec<MetricFamily>) {
+    for mut family in families {
+        match buffer
+            .iter_mut()
+            .find(|buffered| buffered.get_name() == family.get_name())
+        {
+            Some(buffered) => {
+                for metric in family.take_metric() {
+                    match buffered
+                        .mut_metric()
+                        .iter_mut()
+                        .find(|older| output::same_series(older, &metric))
+                    {
+                        Some(older) => *older = metric,
+                        None => buffered.mut_metric().push(metric),
+                    }
+                }
+            }
+
+            None => buffer.push(family),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod merge {
+        use pretty_assertions::assert_eq;
+        use prometheus::{
+            register_gauge_vec_with_registry,
+            Registry,
+        };
+
+        use crate::agent::merge;
+
+        fn collect(target: &str, value: f64) -> Vec<prometheus::proto::MetricFamily> {
+            let registry = Registry::new();
+
+            register_gauge_vec_with_registry!("ping_rtt_seconds", "rtt", &["target"], registry)
+                .unwrap()
+                .with_label_values(&[target])
+                .set(value);
+
+            registry.gather()
+        }
+
+        #[test]
+        fn newest_sample() {
+            let mut buffer = Vec::new();
+
+            merge(&mut buffer, collect("192.0.2.1", 0.5));
+            merge(&mut buffer, collect("192.0.2.2", 1.0));
+            merge(&mut buffer, collect("192.0.2.1", 0.25));
+
+            let values = buffer[0]
+                .get_metric()
+                .iter()
+                .map(|metric| {
+                    (
+                        metric.get_label()[0].get_value(),
+                        metric.get_gauge().get_value(),
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(1, buffer.len());
+            assert_eq!(vec![("192.0.2.1", 0.25), ("192.0.2.2", 1.0)], values);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::probe::{
+    self,
+    filter::Filter,
+    info::{
+        self,
+        Info,
+    },
+    ping::{
+        self,
+        Pinger,
+    },
+    system::{
+        cpu::{
+            self,
+            Cpu,
+        },
+        disk::{
+            self,
+            Disk,
+        },
+        filesystem::{
+            self,
+            Filesystem,
+        },
+        load::{
+            self,
+            Load,
+        },
+        memory::{
+            self,
+            Memory,
+        },
+        netstat::{
+            self,
+            Netstat,
+        },
+        network::{
+            self,
+            Network,
+        },
+        swap::{
+            self,
+            Swap,
+        },
+    },
+    AnyProbe,
+    Labels,
+    Probe,
+};
+
+/// One entry of the configured inputs with the settings that are the same
+/// for every input.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Entry {
+    #[serde(flatten)]
+    pub(crate) input: Input,
+
+    /// How often the input is collected in agent mode. Defaults to the
+    /// interval of the agent.
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) interval: Option<Duration>,
+
+    /// Added to every metric of the input together with the global labels.
+    #[serde(default, skip_serializing_if = "Labels::is_empty")]
+    pub(crate) labels: Labels,
+
+    /// Names of the metric families of the input that are returned.
+    #[serde(default)]
+    pub(crate) metrics: Filter,
+}
+
+impl Entry {
+    /// Returns the probe of the input with the labels and the metric filter
+    /// of the entry.
+    pub(crate) fn probe(&self) -> Arc<dyn AnyProbe> {
+        self.input.probe(self.labels.clone(), self.metrics.clone())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum Input {
+    Info(info::Options),
+    Ping(ping::Options),
+    Cpu(cpu::Options),
+    Load(load::Options),
+    Memory(memory::Options),
+    Swap(swap::Options),
+    Filesystem(filesystem::Options),
+    Disk(disk::Options),
+    Network(network::Options),
+    Netstat(netstat::Options),
+}
+
+impl Input {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Info(_) => "info",
+            Self::Ping(_) => "ping",
+            Self::Cpu(_) => "cpu",
+            Self::Load(_) => "load",
+            Self::Memory(_) => "memory",
+            Self::Swap(_) => "swap",
+            Self::Filesystem(_) => "filesystem",
+            Self::Disk(_) => "disk",
+            Self::Network(_) => "network",
+            Self::Netstat(_) => "netstat",
+        }
+    }
+
+    /// Names of the labels of the metrics of the input, see [`Probe::LABELS`].
+    pub(crate) fn metric_labels(&self) -> &'static [&'static str] {
+        match self {
+            Self::Info(_) => Info::LABELS,
+            Self::Ping(_) => Pinger::LABELS,
+            Self::Cpu(_) => Cpu::LABELS,
+            Self::Load(_) => Load::LABELS,
+            Self::Memory(_) => Memory::LABELS,
+            Self::Swap(_) => Swap::LABELS,
+            Self::Filesystem(_) => Filesystem::LABELS,
+            Self::Disk(_) => Disk::LABELS,
+            Self::Network(_) => Network::LABELS,
+            Self::Netstat(_) => Netstat::LABELS,
+        }
+    }
+
+    pub(crate) fn probe(&self, labels: Labels, metrics: Filter) -> Arc<dyn AnyProbe> {
+        match self {
+            Self::Info(options) => probe::configured::<Info>(options.clone(), labels, metrics),
+            Self::Ping(options) => probe::configured::<Pinger>(options.clone(), labels, metrics),
+            Self::Cpu(options) => probe::configured::<Cpu>(options.clone(), labels, metrics),
+            Self::Load(options) => probe::configured::<Load>(options.clone(), labels, metrics),
+            Self::Memory(options) => probe::configured::<Memory>(options.clone(), labels, metrics),
+            Self::Swap(options) => probe::configured::<Swap>(options.clone(), labels, metrics),
+            Self::Filesystem(options) => {
+                probe::configured::<Filesystem>(options.clone(), labels, metrics)
+            }
+            Self::Disk(options) => probe::configured::<Disk>(options.clone(), labels, metrics),
+            Self::Network(options) => {
+                probe::configured::<Network>(options.clone(), labels, metrics)
+            }
+            Self::Netstat(options) => {
+                probe::configured::<Netstat>(options.clone(), labels, metrics)
+            }
+        }
+    }
+}
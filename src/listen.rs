@@ -0,0 +1,351 @@
+use std::{
+    fmt,
+    future::Future,
+    io,
+    net::{
+        Ipv4Addr,
+        Ipv6Addr,
+        SocketAddr,
+    },
+    os::unix::fs::FileTypeExt,
+    path::PathBuf,
+    pin::Pin,
+    str::FromStr,
+    task::{
+        Context,
+        Poll,
+    },
+    time::Duration,
+};
+
+use anyhow::{
+    bail,
+    Context as _,
+    Error,
+};
+use hyper::server::{
+    accept::Accept,
+    conn::{
+        AddrIncoming,
+        AddrStream,
+    },
+};
+use serde::{
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer,
+};
+use socket2::{
+    Domain,
+    Protocol,
+    Socket,
+    Type,
+};
+use tokio::{
+    io::{
+        AsyncRead,
+        AsyncWrite,
+        ReadBuf,
+    },
+    net::{
+        UnixListener,
+        UnixStream,
+    },
+    time::Sleep,
+};
+
+const DEFAULT_PORT: u16 = 6122;
+
+/// How long to wait before accepting again after an error like running out
+/// of file descriptors, same as hyper does for tcp.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_secs(1);
+
+/// Address to accept connections on. Either a ip socket address like
+/// `127.0.0.1:6122`, `[::]:6122` or a unix domain socket path prefixed with
+/// `unix:` like `unix:/run/callipe.sock`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+#[derive(Debug)]
+enum Listener {
+    Tcp(AddrIncoming),
+    Unix {
+        listener: UnixListener,
+        timeout: Option<Pin<Box<Sleep>>>,
+    },
+}
+
+#[derive(Debug)]
+pub(crate) enum Connection {
+    Tcp(AddrStream),
+    Unix(UnixStream),
+}
+
+/// Accepts connections from any number of listeners.
+#[derive(Debug)]
+pub(crate) struct CombinedIncoming {
+    listeners: Vec<Listener>,
+
+    /// Listener that is polled first so a busy listener can not starve the
+    /// ones after it.
+    next: usize,
+}
+
+pub(crate) fn default_addrs() -> Vec<ListenAddr> {
+    vec![
+        ListenAddr::Tcp(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), DEFAULT_PORT)),
+        ListenAddr::Tcp(SocketAddr::new(Ipv6Addr::LOCALHOST.into(), DEFAULT_PORT)),
+    ]
+}
+
+impl CombinedIncoming {
+    pub(crate) fn bind(addrs: &[ListenAddr]) -> Result<Self, Error> {
+        let listeners = addrs
+            .iter()
+            .map(|addr| Listener::bind(addr).with_context(|| format!("can not listen on {addr}")))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { listeners, next: 0 })
+    }
+}
+
+impl Listener {
+    fn bind(addr: &ListenAddr) -> Result<Self, Error> {
+        match addr {
+            ListenAddr::Tcp(addr) => {
+                let socket = Socket::new(
+                    Domain::for_address(*addr),
+                    Type::STREAM,
+                    Some(Protocol::TCP),
+                )?;
+
+                // Otherwise `[::]` and `0.0.0.0` can not be bound at the same time on
+                // platforms that default to dual stack sockets.
+                if addr.is_ipv6() {
+                    socket.set_only_v6(true)?;
+                }
+
+                socket.set_reuse_address(true)?;
+                socket.set_nonblocking(true)?;
+                socket.bind(&(*addr).into())?;
+                socket.listen(1024)?;
+
+                let listener = tokio::net::TcpListener::from_std(socket.into())?;
+
+                Ok(Self::Tcp(AddrIncoming::from_listener(listener)?))
+            }
+
+            ListenAddr::Unix(path) => {
+                // Remove a stale socket left behind by a previous run but
+                // not one another process is still listening on.
+                if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                    if !metadata.file_type().is_socket() {
+                        bail!("{} exists and is not a socket", path.display())
+                    }
+
+                    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                        bail!("{} is in use by another process", path.display())
+                    }
+
+                    std::fs::remove_file(path)?;
+                }
+
+                Ok(Self::Unix {
+                    listener: UnixListener::bind(path)?,
+                    timeout: None,
+                })
+            }
+        }
+    }
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Connection>> {
+        match self {
+            // Waits on errors by itself.
+            Self::Tcp(incoming) => match Pin::new(incoming).poll_accept(cx) {
+                Poll::Ready(Some(result)) => Poll::Ready(result.map(Connection::Tcp)),
+                Poll::Ready(None) | Poll::Pending => Poll::Pending,
+            },
+
+            Self::Unix { listener, timeout } => {
+                if let Some(sleep) = timeout {
+                    if sleep.as_mut().poll(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+
+                    *timeout = None;
+                }
+
+                match listener.poll_accept(cx) {
+                    Poll::Ready(Ok((stream, _))) => Poll::Ready(Ok(Connection::Unix(stream))),
+
+                    Poll::Ready(Err(err)) => {
+                        // Registers the waker for when the timeout is over.
+                        let mut sleep = Box::pin(tokio::time::sleep(ACCEPT_ERROR_DELAY));
+                        let _ = sleep.as_mut().poll(cx);
+                        *timeout = Some(sleep);
+
+                        Poll::Ready(Err(err))
+                    }
+
+                    Poll::Pending => Poll::Pending,
+                }
+            }
+        }
+    }
+}
+
+impl Accept for CombinedIncoming {
+    type Conn = Connection;
+    type Error = io::Error;
+
+    /// Never fails, errors of a listener are logged so the other listeners
+    /// and later connections keep working.
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let count = self.listeners.len();
+
+        for offset in 0..count {
+            let index = (self.next + offset) % count;
+
+            match self.listeners[index].poll_accept(cx) {
+                Poll::Ready(Ok(connection)) => {
+                    self.next = (index + 1) % count;
+                    return Poll::Ready(Some(Ok(connection)));
+                }
+
+                Poll::Ready(Err(err)) => eprintln!("can not accept connection: {err}"),
+
+                Poll::Pending => {}
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err(format!("missing socket path in listen address {s:?}"));
+            }
+
+            return Ok(Self::Unix(path.into()));
+        }
+
+        s.parse().map(Self::Tcp).map_err(|_| {
+            format!("invalid listen address {s:?}, expected ip:port, [ipv6]:port or unix:/path")
+        })
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(v) => write!(f, "{v}"),
+            Self::Unix(v) => write!(f, "unix:{}", v.display()),
+        }
+    }
+}
+
+impl Serialize for ListenAddr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ListenAddr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod parse {
+        use pretty_assertions::assert_eq;
+        use std::net::SocketAddr;
+
+        use crate::listen::ListenAddr;
+
+        #[test]
+        fn ipv4() {
+            let expected = ListenAddr::Tcp(SocketAddr::from(([0, 0, 0, 0], 6122)));
+            let got = "0.0.0.0:6122".parse::<ListenAddr>().unwrap();
+
+            assert_eq!(expected, got);
+        }
+
+        #[test]
+        fn ipv6() {
+            let expected = ListenAddr::Tcp("[::]:6122".parse().unwrap());
+            let got = "[::]:6122".parse::<ListenAddr>().unwrap();
+
+            assert_eq!(expected, got);
+        }
+
+        #[test]
+        fn unix() {
+            let expected = ListenAddr::Unix("/run/callipe.sock".into());
+            let got = "unix:/run/callipe.sock".parse::<ListenAddr>().unwrap();
+
+            assert_eq!(expected, got);
+        }
+
+        #[test]
+        fn invalid() {
+            assert!("localhost:6122".parse::<ListenAddr>().is_err());
+            assert!("unix:".parse::<ListenAddr>().is_err());
+        }
+    }
+}
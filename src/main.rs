@@ -4,83 +4,135 @@
 #![forbid(unsafe_code)]
 
 use std::{
-    net::{
-        Ipv4Addr,
-        Ipv6Addr,
-        SocketAddr,
-    },
-    pin::Pin,
-    task::{
-        Context,
-        Poll,
-    },
+    path::PathBuf,
+    sync::LazyLock,
 };
 
-use axum::{
-    routing::get,
-    Router,
-};
-use hyper::server::{
-    accept::Accept,
-    conn::AddrIncoming,
+use anyhow::{
+    bail,
+    Error,
 };
+use axum::Router;
+use clap::Parser;
 
+mod agent;
+mod input;
+mod listen;
+mod output;
 mod probe;
+mod settings;
+
+use input::Input;
+use listen::CombinedIncoming;
+use probe::{
+    info::Info,
+    ping::Pinger,
+    system::{
+        cpu::Cpu,
+        disk::Disk,
+        filesystem::Filesystem,
+        load::Load,
+        memory::Memory,
+        netstat::Netstat,
+        network::Network,
+        swap::Swap,
+    },
+};
+use settings::Settings;
 
-struct CombinedIncoming {
-    a: AddrIncoming,
-    b: AddrIncoming,
+/// Metrics collector in the vein of telegraf written in rust.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Opt {
+    /// Path to the config file.
+    #[arg(short, long, default_value = "config.yml")]
+    config: PathBuf,
+
+    /// Collect all inputs once, write them to the outputs and exit instead of
+    /// serving the probes. Exits with an error if an output failed.
+    #[arg(long)]
+    once: bool,
 }
 
-impl Accept for CombinedIncoming {
-    type Conn = <AddrIncoming as Accept>::Conn;
-    type Error = <AddrIncoming as Accept>::Error;
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Cumulative OTLP metrics start when callipe-rs started.
+    LazyLock::force(&probe::format::otlp::STARTED);
 
-    fn poll_accept(
-        mut self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
-        if let Poll::Ready(Some(value)) = Pin::new(&mut self.a).poll_accept(cx) {
-            return Poll::Ready(Some(value));
-        }
+    let opt = Opt::parse();
+    let settings = Settings::from_path(&opt.config)?;
 
-        if let Poll::Ready(Some(value)) = Pin::new(&mut self.b).poll_accept(cx) {
-            return Poll::Ready(Some(value));
+    if opt.once {
+        if settings.outputs.is_empty() {
+            bail!("--once needs at least one output")
         }
 
-        Poll::Pending
+        return agent::once(&settings.inputs, &settings.outputs).await;
+    }
+
+    let app = router(&settings);
+
+    let incoming = CombinedIncoming::bind(&settings.listen)?;
+
+    let server = axum::Server::builder(incoming).serve(app.into_make_service());
+
+    // Agent mode runs next to the server as long as there is somewhere to
+    // write the metrics to.
+    if settings.outputs.is_empty() {
+        server.await?;
+    } else {
+        tokio::try_join!(
+            async { server.await.map_err(Error::from) },
+            agent::run(&settings.agent, &settings.inputs, &settings.outputs),
+        )?;
     }
+
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() {
-    let system_routes = Router::new()
-        .route("/", get(probe::system::handler))
-        .route("/cpu", get(probe::system::cpu::handler))
-        .route("/load", get(probe::system::load::handler))
-        .route("/memory", get(probe::system::memory::handler))
-        .route("/swap", get(probe::system::swap::handler));
-
-    let probe_routes = Router::new()
-        .route("/info", get(probe::info::handler))
-        .route("/ping", get(probe::ping::handler))
-        .nest("/system", system_routes);
-
-    let app = Router::new().nest("/probe", probe_routes);
-
-    let localhost_v4 = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 6122);
-    let incoming_v4 = AddrIncoming::bind(&localhost_v4).unwrap();
-
-    let localhost_v6 = SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 6122);
-    let incoming_v6 = AddrIncoming::bind(&localhost_v6).unwrap();
-
-    let combined = CombinedIncoming {
-        a: incoming_v4,
-        b: incoming_v6,
-    };
-
-    axum::Server::builder(combined)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+/// Only registers the routes for the probes that are configured as inputs.
+fn router(settings: &Settings) -> Router {
+    let mut probe_routes = Router::new();
+    let mut system = probe::system::Routes::default();
+
+    for entry in &settings.inputs {
+        match &entry.input {
+            Input::Info(options) => {
+                probe_routes = probe::route::<Info>(
+                    probe_routes,
+                    options.clone(),
+                    entry.labels.clone(),
+                    entry.metrics.clone(),
+                );
+            }
+            Input::Ping(options) => {
+                probe_routes = probe::route::<Pinger>(
+                    probe_routes,
+                    options.clone(),
+                    entry.labels.clone(),
+                    entry.metrics.clone(),
+                );
+            }
+            Input::Cpu(options) => system.add::<Cpu>(options, &entry.labels, &entry.metrics),
+            Input::Load(options) => system.add::<Load>(options, &entry.labels, &entry.metrics),
+            Input::Memory(options) => system.add::<Memory>(options, &entry.labels, &entry.metrics),
+            Input::Swap(options) => system.add::<Swap>(options, &entry.labels, &entry.metrics),
+            Input::Filesystem(options) => {
+                system.add::<Filesystem>(options, &entry.labels, &entry.metrics);
+            }
+            Input::Disk(options) => system.add::<Disk>(options, &entry.labels, &entry.metrics),
+            Input::Network(options) => {
+                system.add::<Network>(options, &entry.labels, &entry.metrics);
+            }
+            Input::Netstat(options) => {
+                system.add::<Netstat>(options, &entry.labels, &entry.metrics);
+            }
+        }
+    }
+
+    if let Some(system_routes) = system.into_router() {
+        probe_routes = probe_routes.nest("/system", system_routes);
+    }
+
+    Router::new().nest("/probe", probe_routes)
 }
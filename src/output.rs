@@ -0,0 +1,423 @@
+use std::fmt::{
+    self,
+    Debug,
+    Display,
+};
+
+use anyhow::Error;
+use async_trait::async_trait;
+use prometheus::proto::{
+    Metric,
+    MetricFamily,
+    MetricType,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::probe::format::Format;
+
+pub(crate) mod buffer;
+pub(crate) mod file;
+pub(crate) mod graphite;
+pub(crate) mod http;
+pub(crate) mod influx;
+pub(crate) mod otlp;
+pub(crate) mod pushgateway;
+pub(crate) mod remote_write;
+pub(crate) mod socket;
+pub(crate) mod statsd;
+pub(crate) mod stdout;
+
+/// Destinations the metrics collected in agent mode are written to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum Output {
+    Stdout(stdout::Options),
+    File(file::Options),
+    RemoteWrite(remote_write::Options),
+    Influx(influx::Options),
+    Pushgateway(pushgateway::Options),
+    Graphite(graphite::Options),
+    Statsd(statsd::Options),
+    Otlp(otlp::Options),
+}
+
+/// One entry of the configured outputs with the settings that are the same
+/// for every output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Entry {
+    #[serde(flatten)]
+    pub(crate) output: Output,
+
+    /// Keep the flushes that could not be written on disk and write them once
+    /// the output works again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) buffer: Option<buffer::Options>,
+}
+
+/// An output that is ready to be written to.
+#[async_trait]
+pub(crate) trait Write: Debug + Send {
+    /// Writes the metrics collected since the last flush.
+    async fn write(&mut self, families: &[MetricFamily]) -> Result<(), Error>;
+}
+
+/// Context of errors where writing the same metrics again fails the same
+/// way, for example because the receiver rejected them as invalid.
+#[derive(Debug)]
+pub(crate) struct Rejected;
+
+/// A single value of a metric like it appears in the prometheus text format.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Sample {
+    pub(crate) name: String,
+    pub(crate) labels: Vec<(String, String)>,
+    pub(crate) value: f64,
+
+    /// Milliseconds since the unix epoch.
+    pub(crate) timestamp: i64,
+
+    /// Type of the family the sample belongs to.
+    pub(crate) kind: MetricType,
+}
+
+impl Output {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Stdout(_) => "stdout",
+            Self::File(_) => "file",
+            Self::RemoteWrite(_) => "remote_write",
+            Self::Influx(_) => "influx",
+            Self::Pushgateway(_) => "pushgateway",
+            Self::Graphite(_) => "graphite",
+            Self::Statsd(_) => "statsd",
+            Self::Otlp(_) => "otlp",
+        }
+    }
+
+    pub(crate) fn build(&self) -> Result<Box<dyn Write>, Error> {
+        match self {
+            Self::Stdout(options) => Ok(Box::new(stdout::Stdout::new(options.clone()))),
+            Self::File(options) => Ok(Box::new(file::File::new(options.clone()))),
+            Self::RemoteWrite(options) => {
+                Ok(Box::new(remote_write::RemoteWrite::new(options.clone())?))
+            }
+            Self::Influx(options) => Ok(Box::new(influx::Influx::new(options.clone())?)),
+            Self::Pushgateway(options) => {
+                Ok(Box::new(pushgateway::Pushgateway::new(options.clone())?))
+            }
+            Self::Graphite(options) => Ok(Box::new(graphite::Graphite::new(options.clone())?)),
+            Self::Statsd(options) => Ok(Box::new(statsd::Statsd::new(options.clone())?)),
+            Self::Otlp(options) => Ok(Box::new(otlp::Otlp::new(options.clone())?)),
+        }
+    }
+}
+
+impl Entry {
+    pub(crate) fn build(&self) -> Result<Box<dyn Write>, Error> {
+        let output = self.output.build()?;
+
+        match &self.buffer {
+            Some(options) => Ok(Box::new(buffer::Buffered::new(
+                self.output.name(),
+                options.clone(),
+                output,
+            )?)),
+
+            None => Ok(output),
+        }
+    }
+}
+
+impl Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "metrics were rejected")
+    }
+}
+
+/// Returns if `err` happened because the metrics were rejected and will be
+/// rejected again.
+pub(crate) fn is_rejected(err: &Error) -> bool {
+    err.downcast_ref::<Rejected>().is_some()
+}
+
+/// Returns if `a` and `b` are samples of the same series because they have the
+/// same labels.
+pub(crate) fn same_series(a: &Metric, b: &Metric) -> bool {
+    a.get_label().len() == b.get_label().len()
+        && a.get_label()
+            .iter()
+            .zip(b.get_label())
+            .all(|(a, b)| a.get_name() == b.get_name() && a.get_value() == b.get_value())
+}
+
+/// Keeps only the last sample of every series, for outputs that accept just
+/// one sample per series.
+pub(crate) fn latest(families: &mut [MetricFamily]) {
+    for family in families {
+        let mut metrics: Vec<Metric> = Vec::new();
+
+        for metric in family.take_metric() {
+            match metrics.iter_mut().find(|older| same_series(older, &metric)) {
+                Some(older) => *older = metric,
+                None => metrics.push(metric),
+            }
+        }
+
+        family.set_metric(metrics.into());
+    }
+}
+
+/// Encodes `families` for outputs that write one flush after another. Every
+/// flush ends with a newline so JSON is written as JSON lines.
+pub(crate) fn encode(format: Format, families: &[MetricFamily]) -> Result<Vec<u8>, Error> {
+    let mut buffer = format.encode(families)?;
+
+    if buffer.last().is_some_and(|last| *last != b'\n') {
+        buffer.push(b'\n');
+    }
+
+    Ok(buffer)
+}
+
+/// Splits `families` into single samples. Histograms and summaries are split
+/// up into buckets, quantiles, count and sum the same way as in the
+/// prometheus text format. Metrics without their own timestamp get
+/// `timestamp` in milliseconds since the unix epoch.
+pub(crate) fn samples(families: &[MetricFamily], timestamp: i64) -> Vec<Sample> {
+    let mut samples = Vec::new();
+
+    for family in families {
+        let name = family.get_name();
+        let kind = family.get_field_type();
+
+        for metric in family.get_metric() {
+            let timestamp = match metric.get_timestamp_ms() {
+                0 => timestamp,
+                own => own,
+            };
+
+            let mut add = |suffix: &str, extra: Option<(&str, f64)>, value: f64| {
+                let mut labels = metric
+                    .get_label()
+                    .iter()
+                    .map(|label| (label.get_name().to_string(), label.get_value().to_string()))
+                    .collect::<Vec<_>>();
+
+                if let Some((label, bound)) = extra {
+                    labels.push((label.to_string(), number(bound)));
+                }
+
+                samples.push(Sample {
+                    name: format!("{name}{suffix}"),
+                    labels,
+                    value,
+                    timestamp,
+                    kind,
+                });
+            };
+
+            match kind {
+                MetricType::COUNTER => add("", None, metric.get_counter().get_value()),
+                MetricType::GAUGE => add("", None, metric.get_gauge().get_value()),
+                MetricType::UNTYPED => add("", None, metric.get_untyped().get_value()),
+
+                #[allow(clippy::cast_precision_loss)]
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+
+                    for bucket in histogram.get_bucket() {
+                        add(
+                            "_bucket",
+                            Some(("le", bucket.get_upper_bound())),
+                            bucket.get_cumulative_count() as f64,
+                        );
+                    }
+
+                    // The text format adds the +Inf bucket on its own.
+                    if histogram
+                        .get_bucket()
+                        .last()
+                        .map_or(true, |bucket| bucket.get_upper_bound().is_finite())
+                    {
+                        add(
+                            "_bucket",
+                            Some(("le", f64::INFINITY)),
+                            histogram.get_sample_count() as f64,
+                        );
+                    }
+
+                    add("_count", None, histogram.get_sample_count() as f64);
+                    add("_sum", None, histogram.get_sample_sum());
+                }
+
+                #[allow(clippy::cast_precision_loss)]
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+
+                    for quantile in summary.get_quantile() {
+                        add(
+                            "",
+                            Some(("quantile", quantile.get_quantile())),
+                            quantile.get_value(),
+                        );
+                    }
+
+                    add("_count", None, summary.get_sample_count() as f64);
+                    add("_sum", None, summary.get_sample_sum());
+                }
+            }
+        }
+    }
+
+    samples
+}
+
+fn number(value: f64) -> String {
+    if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Metrics and a mock receiver shared by the tests of the outputs.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use std::{
+        net::{
+            SocketAddr,
+            TcpListener,
+        },
+        sync::{
+            Arc,
+            Mutex,
+        },
+    };
+
+    use axum::{
+        body::Bytes,
+        extract::State,
+        http::{
+            HeaderMap,
+            Method,
+            StatusCode,
+            Uri,
+        },
+        Router,
+    };
+    use prometheus::{
+        proto::MetricFamily,
+        register_gauge_vec_with_registry,
+        register_gauge_with_registry,
+        Registry,
+    };
+
+    /// Timestamp of the collected samples in milliseconds since the unix
+    /// epoch.
+    pub(crate) const TIMESTAMP: i64 = 1_700_000_000_000;
+
+    /// A request the mock receiver got.
+    #[derive(Debug)]
+    pub(crate) struct Request {
+        pub(crate) method: Method,
+        pub(crate) uri: String,
+        pub(crate) headers: HeaderMap,
+        pub(crate) body: Bytes,
+    }
+
+    #[derive(Debug, Default)]
+    pub(crate) struct Received {
+        /// How many of the next requests are answered with a server error.
+        pub(crate) failures: usize,
+        pub(crate) requests: Vec<Request>,
+    }
+
+    /// `system_memory_byte` with a `free` and a `used` sample.
+    pub(crate) fn memory() -> Vec<MetricFamily> {
+        let registry = Registry::new();
+
+        let gauge =
+            register_gauge_vec_with_registry!("system_memory_byte", "memory", &["mode"], registry)
+                .unwrap();
+
+        gauge.with_label_values(&["free"]).set(1.0);
+        gauge.with_label_values(&["used"]).set(2.0);
+
+        timestamped(registry.gather())
+    }
+
+    /// `system_load_1` with `value`.
+    pub(crate) fn load(value: f64) -> Vec<MetricFamily> {
+        let registry = Registry::new();
+
+        register_gauge_with_registry!("system_load_1", "load", registry)
+            .unwrap()
+            .set(value);
+
+        timestamped(registry.gather())
+    }
+
+    fn timestamped(mut families: Vec<MetricFamily>) -> Vec<MetricFamily> {
+        for family in &mut families {
+            for metric in family.mut_metric().iter_mut() {
+                metric.set_timestamp_ms(TIMESTAMP);
+            }
+        }
+
+        families
+    }
+
+    /// Serves `app` on a random local port.
+    pub(crate) fn serve(app: Router, http2_only: bool) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .http2_only(http2_only)
+                .serve(app.into_make_service()),
+        );
+
+        addr
+    }
+
+    /// Starts a receiver that fails the first `failures` requests with a
+    /// server error and records every other request.
+    pub(crate) fn receiver(failures: usize) -> (SocketAddr, Arc<Mutex<Received>>) {
+        async fn handler(
+            State(received): State<Arc<Mutex<Received>>>,
+            method: Method,
+            uri: Uri,
+            headers: HeaderMap,
+            body: Bytes,
+        ) -> StatusCode {
+            let mut received = received.lock().unwrap();
+
+            if received.failures > 0 {
+                received.failures -= 1;
+                return StatusCode::SERVICE_UNAVAILABLE;
+            }
+
+            received.requests.push(Request {
+                method,
+                uri: uri.to_string(),
+                headers,
+                body,
+            });
+
+            StatusCode::NO_CONTENT
+        }
+
+        let received = Arc::new(Mutex::new(Received {
+            failures,
+            ..Received::default()
+        }));
+
+        let app = Router::new().fallback(handler).with_state(received.clone());
+
+        (serve(app, false), received)
+    }
+}
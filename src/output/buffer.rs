@@ -0,0 +1,476 @@
+use std::{
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::LazyLock,
+};
+
+use anyhow::{
+    Context,
+    Error,
+};
+use async_trait::async_trait;
+use prometheus::{
+    proto::MetricFamily,
+    IntCounterVec,
+    IntGaugeVec,
+    Opts,
+    Registry,
+};
+use protobuf::{
+    CodedInputStream,
+    Message,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tokio::fs;
+
+use crate::output::{
+    self,
+    Write,
+};
+
+static BUFFER_BYTES: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "callipe_output_buffer_byte",
+            "size of the batches that are buffered on disk",
+        ),
+        &["output", "path"],
+    )
+    .expect("metric is valid")
+});
+
+static BUFFER_BATCHES: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "callipe_output_buffer_batches",
+            "how many batches are buffered on disk",
+        ),
+        &["output", "path"],
+    )
+    .expect("metric is valid")
+});
+
+static BUFFER_DROPPED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "callipe_output_buffer_dropped_batches_total",
+            "batches that were dropped because the buffer was full or the output rejected them",
+        ),
+        &["output", "path"],
+    )
+    .expect("metric is valid")
+});
+
+/// Where and how much an output buffers while it can not write.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Options {
+    /// Directory the batches are stored in, one file per batch. Every output
+    /// needs its own directory.
+    pub(crate) path: PathBuf,
+
+    /// The oldest batches are dropped once the buffer grows larger than this
+    /// many bytes.
+    #[serde(default = "default_max_bytes")]
+    pub(crate) max_bytes: u64,
+}
+
+/// Stores every flush on disk before it is written to `inner` and keeps it
+/// until it was written, so flushes survive failing outputs and restarts.
+/// Buffered flushes are written from oldest to newest.
+#[derive(Debug)]
+pub(crate) struct Buffered {
+    inner: Box<dyn Write>,
+    queue: Queue,
+}
+
+/// The batches that are stored on disk.
+#[derive(Debug)]
+struct Queue {
+    name: &'static str,
+    options: Options,
+
+    /// Sequence number of the next batch so the files sort in order.
+    next: u64,
+}
+
+/// A flush that is stored on disk.
+#[derive(Debug)]
+struct Batch {
+    path: PathBuf,
+    size: u64,
+}
+
+fn default_max_bytes() -> u64 {
+    100 * 1024 * 1024
+}
+
+/// Registers the metrics about the buffers of all outputs in `registry`.
+pub(crate) fn register(registry: &Registry) -> Result<(), Error> {
+    registry.register(Box::new(BUFFER_BYTES.clone()))?;
+    registry.register(Box::new(BUFFER_BATCHES.clone()))?;
+    registry.register(Box::new(BUFFER_DROPPED.clone()))?;
+
+    Ok(())
+}
+
+impl Buffered {
+    pub(super) fn new(
+        name: &'static str,
+        options: Options,
+        inner: Box<dyn Write>,
+    ) -> Result<Self, Error> {
+        std::fs::create_dir_all(&options.path)
+            .with_context(|| format!("can not create buffer {}", options.path.display()))?;
+
+        let mut queue = Queue {
+            name,
+            options,
+            next: 0,
+        };
+
+        // Batches that were buffered before a restart are kept.
+        let mut last = None;
+
+        for entry in std::fs::read_dir(&queue.options.path)? {
+            if let Some(sequence) = sequence(&entry?.path()) {
+                last = last.max(Some(sequence));
+            }
+        }
+
+        queue.next = last.map_or(0, |last| last + 1);
+        BUFFER_DROPPED.with_label_values(&queue.labels());
+        queue.batches()?;
+
+        Ok(Self { inner, queue })
+    }
+
+    /// Writes the buffered batches from oldest to newest. Stops at the first
+    /// batch that can not be written for now. Batches the output rejected are
+    /// dropped as they would block the buffer forever, the first rejection is
+    /// returned after all other batches were written.
+    async fn replay(&mut self) -> Result<(), Error> {
+        let mut rejected = None;
+
+        for batch in self.queue.batches()? {
+            let families = match read(&batch.path).await {
+                Ok(families) => families,
+
+                Err(err) => {
+                    eprintln!(
+                        "dropping unreadable batch {}: {err:#}",
+                        batch.path.display()
+                    );
+                    BUFFER_DROPPED.with_label_values(&self.queue.labels()).inc();
+                    fs::remove_file(&batch.path).await?;
+                    continue;
+                }
+            };
+
+            match self.inner.write(&families).await {
+                Ok(()) => {}
+
+                Err(err) if output::is_rejected(&err) => {
+                    BUFFER_DROPPED.with_label_values(&self.queue.labels()).inc();
+                    rejected.get_or_insert(
+                        err.context(format!("dropped batch {}", batch.path.display())),
+                    );
+                }
+
+                Err(err) => {
+                    self.queue.batches()?;
+                    return Err(err);
+                }
+            }
+
+            fs::remove_file(&batch.path).await?;
+        }
+
+        self.queue.batches()?;
+
+        rejected.map_or(Ok(()), Err)
+    }
+}
+
+impl Queue {
+    fn labels(&self) -> [&str; 2] {
+        [self.name, self.options.path.to_str().unwrap_or_default()]
+    }
+
+    /// Lists the buffered batches from oldest to newest and updates the
+    /// metrics.
+    fn batches(&self) -> Result<Vec<Batch>, Error> {
+        let mut batches = Vec::new();
+
+        for entry in std::fs::read_dir(&self.options.path)? {
+            let entry = entry?;
+            let path = entry.path();
+
+            if let Some(sequence) = sequence(&path) {
+                let size = entry.metadata()?.len();
+                batches.push((sequence, Batch { path, size }));
+            }
+        }
+
+        batches.sort_by_key(|(sequence, _)| *sequence);
+
+        let batches = batches
+            .into_iter()
+            .map(|(_, batch)| batch)
+            .collect::<Vec<_>>();
+
+        let size = batches.iter().map(|batch| batch.size).sum::<u64>();
+
+        BUFFER_BYTES
+            .with_label_values(&self.labels())
+            .set(i64::try_from(size).unwrap_or(i64::MAX));
+        BUFFER_BATCHES
+            .with_label_values(&self.labels())
+            .set(i64::try_from(batches.len()).unwrap_or(i64::MAX));
+
+        Ok(batches)
+    }
+
+    /// Stores `families` as the newest batch and drops the oldest batches if
+    /// the buffer is full.
+    async fn push(&mut self, families: &[MetricFamily]) -> Result<(), Error> {
+        let mut buffer = Vec::new();
+
+        for family in families {
+            family.write_length_delimited_to_vec(&mut buffer)?;
+        }
+
+        let path = self.options.path.join(format!("{:020}.batch", self.next));
+        let temporary = path.with_extension("tmp");
+        self.next += 1;
+
+        // A batch that was only partially written before a crash should not
+        // be replayed.
+        fs::write(&temporary, buffer)
+            .await
+            .with_context(|| format!("can not write {}", temporary.display()))?;
+        fs::rename(&temporary, &path).await?;
+
+        let batches = self.batches()?;
+        let mut size = batches.iter().map(|batch| batch.size).sum::<u64>();
+
+        for batch in batches {
+            if size <= self.options.max_bytes {
+                break;
+            }
+
+            fs::remove_file(&batch.path).await?;
+            size -= batch.size;
+            BUFFER_DROPPED.with_label_values(&self.labels()).inc();
+        }
+
+        self.batches()?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Write for Buffered {
+    async fn write(&mut self, families: &[MetricFamily]) -> Result<(), Error> {
+        if let Err(err) = self.queue.push(families).await {
+            // The flush is at least written directly if the disk is full.
+            eprintln!("output {} can not buffer metrics: {err:#}", self.queue.name);
+            self.replay().await?;
+
+            return self.inner.write(families).await;
+        }
+
+        self.replay().await.map_err(|err| {
+            if output::is_rejected(&err) {
+                err
+            } else {
+                err.context("metrics are buffered on disk")
+            }
+        })
+    }
+}
+
+fn sequence(path: &Path) -> Option<u64> {
+    if path.extension()? != "batch" {
+        return None;
+    }
+
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+async fn read(path: &Path) -> Result<Vec<MetricFamily>, Error> {
+    let buffer = fs::read(path).await?;
+    let mut input = CodedInputStream::from_bytes(&buffer);
+    let mut families = Vec::new();
+
+    while !input.eof()? {
+        families.push(input.read_message()?);
+    }
+
+    Ok(families)
+}
+
+#[cfg(test)]
+mod tests {
+    mod write {
+        use std::sync::{
+            atomic::{
+                AtomicBool,
+                Ordering,
+            },
+            Arc,
+            Mutex,
+        };
+
+        use anyhow::{
+            anyhow,
+            bail,
+            Error,
+        };
+        use async_trait::async_trait;
+        use pretty_assertions::assert_eq;
+        use prometheus::proto::MetricFamily;
+
+        use crate::output::{
+            buffer::{
+                Buffered,
+                Options,
+                BUFFER_BATCHES,
+                BUFFER_DROPPED,
+            },
+            fixtures::load,
+            Rejected,
+            Write,
+        };
+
+        /// Records the written values, fails while `down` is set and
+        /// rejects the value in `reject`.
+        #[derive(Debug, Clone, Default)]
+        struct Endpoint {
+            down: Arc<AtomicBool>,
+            reject: Arc<Mutex<Option<f64>>>,
+            written: Arc<Mutex<Vec<f64>>>,
+        }
+
+        #[async_trait]
+        impl Write for Endpoint {
+            async fn write(&mut self, families: &[MetricFamily]) -> Result<(), Error> {
+                if self.down.load(Ordering::SeqCst) {
+                    bail!("endpoint is down")
+                }
+
+                let value = families[0].get_metric()[0].get_gauge().get_value();
+
+                if *self.reject.lock().unwrap() == Some(value) {
+                    return Err(anyhow!("sample is out of bounds").context(Rejected));
+                }
+
+                self.written.lock().unwrap().push(value);
+
+                Ok(())
+            }
+        }
+
+        fn buffered(endpoint: &Endpoint, options: &Options) -> Buffered {
+            Buffered::new("test", options.clone(), Box::new(endpoint.clone())).unwrap()
+        }
+
+        #[tokio::test]
+        async fn replay_after_restart() {
+            let directory = tempfile::tempdir().unwrap();
+            let options = Options {
+                path: directory.path().to_path_buf(),
+                max_bytes: 1024 * 1024,
+            };
+
+            let endpoint = Endpoint::default();
+            endpoint.down.store(true, Ordering::SeqCst);
+
+            let mut output = buffered(&endpoint, &options);
+            assert!(output.write(&load(1.0)).await.is_err());
+            assert!(output.write(&load(2.0)).await.is_err());
+
+            let labels = ["test", directory.path().to_str().unwrap()];
+            assert_eq!(2, BUFFER_BATCHES.with_label_values(&labels).get());
+
+            drop(output);
+            endpoint.down.store(false, Ordering::SeqCst);
+
+            let mut output = buffered(&endpoint, &options);
+            output.write(&load(3.0)).await.unwrap();
+
+            assert_eq!(vec![1.0, 2.0, 3.0], *endpoint.written.lock().unwrap());
+            assert_eq!(0, BUFFER_BATCHES.with_label_values(&labels).get());
+        }
+
+        #[tokio::test]
+        async fn drop_oldest() {
+            let directory = tempfile::tempdir().unwrap();
+            let size = {
+                let mut buffer = Vec::new();
+                protobuf::Message::write_length_delimited_to_vec(&load(1.0)[0], &mut buffer)
+                    .unwrap();
+                buffer.len() as u64
+            };
+
+            let endpoint = Endpoint::default();
+            endpoint.down.store(true, Ordering::SeqCst);
+
+            let mut output = buffered(
+                &endpoint,
+                &Options {
+                    path: directory.path().to_path_buf(),
+                    max_bytes: size * 3,
+                },
+            );
+
+            for value in [1.0, 2.0, 3.0] {
+                assert!(output.write(&load(value)).await.is_err());
+            }
+
+            endpoint.down.store(false, Ordering::SeqCst);
+            output.write(&load(4.0)).await.unwrap();
+
+            let labels = ["test", directory.path().to_str().unwrap()];
+
+            assert_eq!(vec![2.0, 3.0, 4.0], *endpoint.written.lock().unwrap());
+            assert_eq!(1, BUFFER_DROPPED.with_label_values(&labels).get());
+        }
+
+        #[tokio::test]
+        async fn skip_rejected() {
+            let directory = tempfile::tempdir().unwrap();
+
+            let endpoint = Endpoint::default();
+            endpoint.down.store(true, Ordering::SeqCst);
+
+            let mut output = buffered(
+                &endpoint,
+                &Options {
+                    path: directory.path().to_path_buf(),
+                    max_bytes: 1024 * 1024,
+                },
+            );
+
+            assert!(output.write(&load(1.0)).await.is_err());
+            assert!(output.write(&load(2.0)).await.is_err());
+
+            endpoint.down.store(false, Ordering::SeqCst);
+            *endpoint.reject.lock().unwrap() = Some(1.0);
+
+            assert!(output.write(&load(3.0)).await.is_err());
+            output.write(&load(4.0)).await.unwrap();
+
+            let labels = ["test", directory.path().to_str().unwrap()];
+
+            assert_eq!(vec![2.0, 3.0, 4.0], *endpoint.written.lock().unwrap());
+            assert_eq!(1, BUFFER_DROPPED.with_label_values(&labels).get());
+            assert_eq!(0, BUFFER_BATCHES.with_label_values(&labels).get());
+        }
+    }
+}
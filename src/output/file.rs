@@ -0,0 +1,305 @@
+use std::{
+    ffi::OsString,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use anyhow::{
+    Context,
+    Error,
+};
+use async_trait::async_trait;
+use prometheus::proto::MetricFamily;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tokio::{
+    fs,
+    io::AsyncWriteExt,
+};
+
+use crate::{
+    output::{
+        self,
+        Write,
+    },
+    probe::format::Format,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Options {
+    pub(crate) path: PathBuf,
+
+    #[serde(default = "default_format")]
+    pub(crate) format: Format,
+
+    #[serde(default)]
+    pub(crate) mode: Mode,
+
+    /// Rotate the file before it grows larger than this many bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) max_bytes: Option<u64>,
+
+    /// Rotate the file after it was written to for this long.
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) max_age: Option<Duration>,
+
+    /// How many rotated files are kept next to the file as `<path>.1`,
+    /// `<path>.2` and so on. `<path>.1` is the newest.
+    #[serde(default = "default_retention")]
+    pub(crate) retention: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Mode {
+    /// Append every flush to the file and rotate it.
+    #[default]
+    Append,
+
+    /// Atomically replace the file with the latest flush without timestamps,
+    /// for example for the textfile collector of the node exporter.
+    Replace,
+}
+
+#[derive(Debug)]
+pub(crate) struct File {
+    options: Options,
+    current: Option<Current>,
+}
+
+/// The file that is appended to.
+#[derive(Debug)]
+struct Current {
+    file: fs::File,
+    size: u64,
+    opened: Instant,
+}
+
+fn default_format() -> Format {
+    Format::Influx
+}
+
+fn default_retention() -> usize {
+    5
+}
+
+impl File {
+    pub(super) fn new(options: Options) -> Self {
+        Self {
+            options,
+            current: None,
+        }
+    }
+
+    async fn append(&mut self, buffer: &[u8]) -> Result<(), Error> {
+        let path = &self.options.path;
+
+        let rotate = self.current.as_ref().is_some_and(|current| {
+            let too_large = self.options.max_bytes.is_some_and(|max_bytes| {
+                current.size > 0 && current.size + buffer.len() as u64 > max_bytes
+            });
+
+            let too_old = self
+                .options
+                .max_age
+                .is_some_and(|max_age| current.opened.elapsed() >= max_age);
+
+            too_large || too_old
+        });
+
+        if rotate {
+            self.current = None;
+            rotate_files(path, self.options.retention).await?;
+        }
+
+        // A file that failed to be written to is opened again on the next
+        // flush.
+        let mut current = if let Some(current) = self.current.take() {
+            current
+        } else {
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await
+                .with_context(|| format!("can not open {}", path.display()))?;
+
+            Current {
+                size: file.metadata().await?.len(),
+                file,
+                opened: Instant::now(),
+            }
+        };
+
+        current.file.write_all(buffer).await?;
+        current.file.flush().await?;
+        current.size += buffer.len() as u64;
+
+        self.current = Some(current);
+
+        Ok(())
+    }
+
+    async fn replace(&self, families: &[MetricFamily]) -> Result<(), Error> {
+        let path = &self.options.path;
+
+        // The file is a snapshot of the latest flush and some readers like
+        // the node exporter reject timestamps and repeated series.
+        let mut families = families.to_vec();
+        output::latest(&mut families);
+
+        for family in &mut families {
+            for metric in family.mut_metric().iter_mut() {
+                metric.clear_timestamp_ms();
+            }
+        }
+
+        let buffer = output::encode(self.options.format, &families)?;
+
+        // Readers should never see a partially written file.
+        let temporary = suffixed(path, "tmp");
+
+        fs::write(&temporary, buffer)
+            .await
+            .with_context(|| format!("can not write {}", temporary.display()))?;
+
+        fs::rename(&temporary, path)
+            .await
+            .with_context(|| format!("can not replace {}", path.display()))
+    }
+}
+
+#[async_trait]
+impl Write for File {
+    async fn write(&mut self, families: &[MetricFamily]) -> Result<(), Error> {
+        match self.options.mode {
+            Mode::Append => {
+                let buffer = output::encode(self.options.format, families)?;
+                self.append(&buffer).await
+            }
+
+            Mode::Replace => self.replace(families).await,
+        }
+    }
+}
+
+/// Moves `<path>.<n>` to `<path>.<n + 1>` and `path` to `<path>.1`. The
+/// oldest file is overwritten once there are `retention` rotated files.
+async fn rotate_files(path: &Path, retention: usize) -> Result<(), Error> {
+    if retention == 0 {
+        return fs::remove_file(path)
+            .await
+            .with_context(|| format!("can not remove {}", path.display()));
+    }
+
+    for index in (1..retention).rev() {
+        let from = suffixed(path, &index.to_string());
+
+        if fs::try_exists(&from).await? {
+            fs::rename(&from, suffixed(path, &(index + 1).to_string())).await?;
+        }
+    }
+
+    fs::rename(path, suffixed(path, "1"))
+        .await
+        .with_context(|| format!("can not rotate {}", path.display()))
+}
+
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".");
+    name.push(suffix);
+
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    mod write {
+        use pretty_assertions::assert_eq;
+
+        use crate::{
+            output::{
+                file::{
+                    File,
+                    Mode,
+                    Options,
+                },
+                fixtures::load,
+                Write,
+            },
+            probe::format::Format,
+        };
+
+        #[tokio::test]
+        async fn rotate() {
+            let directory = tempfile::tempdir().unwrap();
+            let path = directory.path().join("metrics.influx");
+
+            let mut output = File::new(Options {
+                path: path.clone(),
+                format: Format::Influx,
+                mode: Mode::Append,
+                max_bytes: Some(80),
+                max_age: None,
+                retention: 2,
+            });
+
+            for value in [1.0, 2.0, 3.0, 4.0, 5.0] {
+                output.write(&load(value)).await.unwrap();
+            }
+
+            let read = |suffix: &str| {
+                std::fs::read_to_string(format!("{}{suffix}", path.display())).unwrap()
+            };
+
+            let line = |value: u8| format!("system_load 1={value} 1700000000000000000\n");
+
+            assert_eq!(line(5), read(""));
+            assert_eq!(format!("{}{}", line(3), line(4)), read(".1"));
+            assert_eq!(format!("{}{}", line(1), line(2)), read(".2"));
+            assert!(!directory.path().join("metrics.influx.3").exists());
+        }
+
+        #[tokio::test]
+        async fn replace() {
+            let directory = tempfile::tempdir().unwrap();
+            let path = directory.path().join("callipe.prom");
+
+            let mut output = File::new(Options {
+                path: path.clone(),
+                format: Format::Prometheus,
+                mode: Mode::Replace,
+                max_bytes: None,
+                max_age: None,
+                retention: 0,
+            });
+
+            output.write(&load(1.0)).await.unwrap();
+
+            // Both collections of the input within one flush.
+            let mut families = load(2.0);
+            let newer = load(3.0)[0].get_metric()[0].clone();
+            families[0].mut_metric().push(newer);
+
+            output.write(&families).await.unwrap();
+
+            let expected =
+                "# HELP system_load_1 load\n# TYPE system_load_1 gauge\nsystem_load_1 3\n";
+
+            assert_eq!(expected, std::fs::read_to_string(&path).unwrap());
+        }
+    }
+}
@@ -0,0 +1,403 @@
+use std::{
+    collections::HashMap,
+    time::Duration,
+};
+
+use anyhow::{
+    bail,
+    Error,
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use prometheus::proto::MetricFamily;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::output::{
+    self,
+    socket::{
+        self,
+        Protocol,
+    },
+    Sample,
+    Write,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Options {
+    /// Address of the carbon plaintext receiver, for example
+    /// `graphite:2003`.
+    pub(crate) address: String,
+
+    #[serde(default)]
+    pub(crate) protocol: Protocol,
+
+    /// How the path of a sample is built, see [`Template`].
+    #[serde(default = "default_template")]
+    pub(crate) template: String,
+
+    #[serde(default = "default_timeout", with = "humantime_serde")]
+    pub(crate) timeout: Duration,
+}
+
+#[derive(Debug)]
+pub(crate) struct Graphite {
+    address: String,
+    protocol: Protocol,
+    template: Template,
+    timeout: Duration,
+}
+
+/// Dot separated path with placeholders. `{name}` is replaced with the metric
+/// name, `{<label>}` with the value of that label and `{labels}` with the
+/// values of all other labels sorted by label name. Everything else is kept
+/// as is, for example `servers.web1.{name}.{labels}`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Template {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Text(String),
+    Name,
+    Labels,
+    Label(String),
+}
+
+fn default_template() -> String {
+    "{name}.{labels}".to_string()
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+impl Graphite {
+    pub(super) fn new(options: Options) -> Result<Self, Error> {
+        Ok(Self {
+            address: options.address,
+            protocol: options.protocol,
+            template: options.template.parse()?,
+            timeout: options.timeout,
+        })
+    }
+}
+
+#[async_trait]
+impl Write for Graphite {
+    async fn write(&mut self, families: &[MetricFamily]) -> Result<(), Error> {
+        let samples = output::samples(families, Utc::now().timestamp_millis());
+
+        let lines = self
+            .template
+            .paths(samples)
+            .into_iter()
+            .map(|(path, sample)| format!("{path} {} {}\n", sample.value, sample.timestamp / 1000))
+            .collect::<Vec<_>>();
+
+        socket::send(self.protocol, &self.address, &lines, self.timeout).await
+    }
+}
+
+impl std::str::FromStr for Template {
+    type Err = Error;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let segments = template
+            .split('.')
+            .map(|segment| {
+                match segment
+                    .strip_prefix('{')
+                    .and_then(|segment| segment.strip_suffix('}'))
+                {
+                    Some("name") => Segment::Name,
+                    Some("labels") => Segment::Labels,
+                    Some(label) => Segment::Label(label.to_string()),
+                    None => Segment::Text(segment.to_string()),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if !segments.contains(&Segment::Name) {
+            bail!("template {template:?} has to contain {{name}}")
+        }
+
+        Ok(Self { segments })
+    }
+}
+
+impl Template {
+    /// Builds the path of `sample`. Labels that are missing or empty are left
+    /// out and characters that have a meaning in graphite paths are replaced
+    /// with underscores.
+    pub(crate) fn render(&self, sample: &Sample) -> String {
+        let label = |name: &str| {
+            sample
+                .labels
+                .iter()
+                .find(|(label, _)| label == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        let mut path = Vec::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => path.push(text.clone()),
+                Segment::Name => path.push(sanitize(&sample.name)),
+                Segment::Label(name) => path.extend(label(name).map(sanitize)),
+
+                Segment::Labels => {
+                    let mut labels = sample
+                        .labels
+                        .iter()
+                        .filter(|(name, _)| !self.segments.contains(&Segment::Label(name.clone())))
+                        .collect::<Vec<_>>();
+
+                    labels.sort();
+                    path.extend(labels.into_iter().map(|(_, value)| sanitize(value)));
+                }
+            }
+        }
+
+        path.retain(|segment| !segment.is_empty());
+        path.join(".")
+    }
+
+    /// Renders the path of every finite sample, neither graphite nor statsd
+    /// have a representation for NaN and infinity. Series the template
+    /// renders the same path for are skipped as their values would be mixed
+    /// up, the other series are still returned.
+    pub(crate) fn paths(&self, samples: Vec<Sample>) -> Vec<(String, Sample)> {
+        let samples = samples
+            .into_iter()
+            .filter(|sample| sample.value.is_finite())
+            .map(|sample| (self.render(&sample), sample))
+            .collect::<Vec<_>>();
+
+        let mut series = HashMap::<&str, &Sample>::new();
+        let mut collisions = HashMap::new();
+
+        for (path, sample) in &samples {
+            match series.get(path.as_str()) {
+                Some(other) if other.name != sample.name || other.labels != sample.labels => {
+                    collisions.insert(path.clone(), (other.name.clone(), other.labels.clone()));
+                }
+
+                Some(_) => {}
+                None => {
+                    series.insert(path, sample);
+                }
+            }
+        }
+
+        for (path, (name, labels)) in &collisions {
+            eprintln!(
+                "template renders {path} for more than one series like {name} {labels:?}, \
+                 skipping them, add the labels that differ to the template"
+            );
+        }
+
+        samples
+            .into_iter()
+            .filter(|(path, _)| !collisions.contains_key(path))
+            .collect()
+    }
+}
+
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | ':') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    mod template {
+        use pretty_assertions::assert_eq;
+        use prometheus::proto::MetricType;
+
+        use crate::output::{
+            graphite::Template,
+            Sample,
+        };
+
+        fn sample() -> Sample {
+            Sample {
+                name: "system_filesystem_free_byte".to_string(),
+                labels: vec![
+                    ("mountpoint".to_string(), "/var/lib".to_string()),
+                    ("device".to_string(), "sda1".to_string()),
+                    ("fs_type".to_string(), String::new()),
+                ],
+                value: 1.0,
+                timestamp: 0,
+                kind: MetricType::GAUGE,
+            }
+        }
+
+        #[test]
+        fn default() {
+            let template: Template = "{name}.{labels}".parse().unwrap();
+
+            assert_eq!(
+                "system_filesystem_free_byte.sda1._var_lib",
+                template.render(&sample())
+            );
+        }
+
+        #[test]
+        fn labels() {
+            let template: Template = "servers.web1.{mountpoint}.{name}.{labels}".parse().unwrap();
+
+            assert_eq!(
+                "servers.web1._var_lib.system_filesystem_free_byte.sda1",
+                template.render(&sample())
+            );
+        }
+
+        #[test]
+        fn without_name() {
+            assert!("servers.{labels}".parse::<Template>().is_err());
+        }
+
+        #[test]
+        fn collision() {
+            let template: Template = "servers.{name}.{device}".parse().unwrap();
+
+            let other = Sample {
+                labels: vec![("device".to_string(), "sda2".to_string())],
+                ..sample()
+            };
+
+            let without_device = |mountpoint: &str| Sample {
+                labels: vec![("mountpoint".to_string(), mountpoint.to_string())],
+                ..sample()
+            };
+
+            let paths = template
+                .paths(vec![
+                    sample(),
+                    other,
+                    without_device("/"),
+                    without_device("/home"),
+                ])
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect::<Vec<_>>();
+
+            // Both filesystems without a device label render the same path.
+            assert_eq!(
+                vec![
+                    "servers.system_filesystem_free_byte.sda1",
+                    "servers.system_filesystem_free_byte.sda2",
+                ],
+                paths
+            );
+        }
+    }
+
+    mod write {
+        use std::time::Duration;
+
+        use pretty_assertions::assert_eq;
+        use prometheus::{
+            register_gauge_vec_with_registry,
+            Registry,
+        };
+        use tokio::{
+            io::AsyncReadExt,
+            net::{
+                TcpListener,
+                UdpSocket,
+            },
+        };
+
+        use crate::output::{
+            fixtures,
+            graphite::{
+                Graphite,
+                Options,
+            },
+            socket::Protocol,
+            Write,
+        };
+
+        const EXPECTED: &str = "\
+system_load.1 0.5 1700000000
+system_load.5 0.25 1700000000
+";
+
+        fn collect() -> Vec<prometheus::proto::MetricFamily> {
+            let registry = Registry::new();
+
+            let gauge =
+                register_gauge_vec_with_registry!("system_load", "load", &["period"], registry)
+                    .unwrap();
+
+            gauge.with_label_values(&["1"]).set(0.5);
+            gauge.with_label_values(&["5"]).set(0.25);
+            gauge.with_label_values(&["15"]).set(f64::NAN);
+
+            let mut families = registry.gather();
+
+            for metric in families[0].mut_metric().iter_mut() {
+                metric.set_timestamp_ms(fixtures::TIMESTAMP);
+            }
+
+            families
+        }
+
+        fn options(protocol: Protocol, address: String) -> Options {
+            Options {
+                address,
+                protocol,
+                template: "{name}.{labels}".to_string(),
+                timeout: Duration::from_secs(5),
+            }
+        }
+
+        #[tokio::test]
+        async fn tcp() {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap().to_string();
+
+            let mut output = Graphite::new(options(Protocol::Tcp, address)).unwrap();
+            let families = collect();
+
+            let (written, received) = tokio::join!(output.write(&families), async {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut received = String::new();
+                stream.read_to_string(&mut received).await.unwrap();
+                received
+            });
+
+            written.unwrap();
+            assert_eq!(EXPECTED, received);
+        }
+
+        #[tokio::test]
+        async fn udp() {
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let address = socket.local_addr().unwrap().to_string();
+
+            let mut output = Graphite::new(options(Protocol::Udp, address)).unwrap();
+            output.write(&collect()).await.unwrap();
+
+            let mut buffer = [0; 1500];
+            let length = socket.recv(&mut buffer).await.unwrap();
+
+            assert_eq!(EXPECTED, String::from_utf8_lossy(&buffer[..length]));
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use std::{
+    future::Future,
+    num::NonZeroUsize,
+    time::Duration,
+};
+
+use anyhow::{
+    anyhow,
+    bail,
+    Context,
+    Error,
+};
+use base64::{
+    engine::general_purpose::STANDARD,
+    Engine,
+};
+use reqwest::{
+    header::{
+        self,
+        HeaderValue,
+    },
+    RequestBuilder,
+    StatusCode,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tokio::time;
+
+use crate::output::{
+    self,
+    Rejected,
+};
+
+/// Settings shared by the outputs that push metrics over HTTP.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Options {
+    /// How long a single request can take.
+    #[serde(with = "humantime_serde")]
+    pub(crate) timeout: Duration,
+
+    /// How often a request is retried after a connection error or a server
+    /// error before the metrics are dropped.
+    pub(crate) retries: u32,
+
+    /// How long to wait before the first retry. Doubles with every retry up
+    /// to `max_backoff`.
+    #[serde(with = "humantime_serde")]
+    pub(crate) backoff: Duration,
+
+    #[serde(with = "humantime_serde")]
+    pub(crate) max_backoff: Duration,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) basic_auth: Option<BasicAuth>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) bearer_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct BasicAuth {
+    pub(crate) username: String,
+    pub(crate) password: Option<String>,
+}
+
+/// Why an attempt of [`retry`] failed.
+#[derive(Debug)]
+pub(crate) enum Attempt {
+    /// The error might go away by trying again.
+    Retry(Error),
+
+    /// Trying again will fail the same way.
+    Fail(Error),
+}
+
+/// Sends requests with the configured authentication and retries them with
+/// an exponential backoff.
+#[derive(Debug)]
+pub(crate) struct Client {
+    inner: reqwest::Client,
+    options: Options,
+    authorization: Option<HeaderValue>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            retries: 3,
+            backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            basic_auth: None,
+            bearer_token: None,
+        }
+    }
+}
+
+impl Options {
+    /// Value of the `authorization` header for `basic_auth` or
+    /// `bearer_token`.
+    pub(crate) fn authorization(&self) -> Result<Option<HeaderValue>, Error> {
+        let value = match (&self.basic_auth, &self.bearer_token) {
+            (Some(_), Some(_)) => bail!("basic_auth and bearer_token can not be used together"),
+            (None, None) => return Ok(None),
+
+            (Some(auth), None) => {
+                let credentials = format!(
+                    "{}:{}",
+                    auth.username,
+                    auth.password.as_deref().unwrap_or_default()
+                );
+
+                format!("Basic {}", STANDARD.encode(credentials))
+            }
+
+            (None, Some(token)) => format!("Bearer {token}"),
+        };
+
+        let mut value = HeaderValue::try_from(value)
+            .context("basic_auth or bearer_token can not be used in a header")?;
+        value.set_sensitive(true);
+
+        Ok(Some(value))
+    }
+}
+
+impl Client {
+    pub(super) fn new(options: Options) -> Result<Self, Error> {
+        let authorization = options.authorization()?;
+
+        let inner = reqwest::Client::builder()
+            .user_agent(concat!("callipe-rs/", env!("CARGO_PKG_VERSION")))
+            .timeout(options.timeout)
+            .build()
+            .context("can not create http client")?;
+
+        Ok(Self {
+            inner,
+            options,
+            authorization,
+        })
+    }
+
+    /// Sends the request built by `request` until it succeeds, fails with a
+    /// client error or the retries are used up. `request` is called again
+    /// for every attempt.
+    pub(super) async fn send<F>(&self, request: F) -> Result<(), Error>
+    where
+        F: Fn(&reqwest::Client) -> RequestBuilder + Send + Sync,
+    {
+        retry(&self.options, || async {
+            let response = self
+                .authenticate(request(&self.inner))
+                .send()
+                .await
+                .map_err(|err| Attempt::Retry(err.into()))?;
+
+            let status = response.status();
+
+            if status.is_success() {
+                return Ok(());
+            }
+
+            let body = response.text().await.unwrap_or_default();
+            let err = anyhow!("server responded with {status}: {}", body.trim());
+
+            // Client errors will not go away by sending the same request
+            // again.
+            if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+                Err(Attempt::Retry(err))
+            } else {
+                Err(Attempt::Fail(err))
+            }
+        })
+        .await
+    }
+
+    fn authenticate(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.authorization {
+            Some(authorization) => request.header(header::AUTHORIZATION, authorization.clone()),
+            None => request,
+        }
+    }
+}
+
+/// Runs `attempt` until it succeeds, fails with [`Attempt::Fail`] or the
+/// retries of `options` are used up. Waits with an exponential backoff
+/// between the attempts.
+pub(super) async fn retry<F, Fut, T>(options: &Options, attempt: F) -> Result<T, Error>
+where
+    F: Fn() -> Fut + Send + Sync,
+    Fut: Future<Output = Result<T, Attempt>> + Send,
+{
+    let mut backoff = options.backoff;
+    let mut attempts = 0;
+
+    loop {
+        let err = match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(Attempt::Fail(err)) => return Err(err.context(Rejected)),
+            Err(Attempt::Retry(err)) => err,
+        };
+
+        if attempts >= options.retries {
+            return Err(err.context(format!("request failed {} times", attempts + 1)));
+        }
+
+        time::sleep(backoff).await;
+        backoff = (backoff * 2).min(options.max_backoff);
+        attempts += 1;
+    }
+}
+
+/// Sends `items` in batches of up to `size` with `send`. A failed batch does
+/// not keep the others from being sent. Only fails as [`Rejected`] if every
+/// failed batch was rejected, so a buffered flush is not dropped while some
+/// of its batches can still be delivered by trying again.
+pub(super) async fn send_batches<'a, T, F, Fut>(
+    items: &'a [T],
+    size: NonZeroUsize,
+    mut send: F,
+) -> Result<(), Error>
+where
+    F: FnMut(&'a [T]) -> Fut,
+    Fut: Future<Output = Result<(), Error>>,
+{
+    let mut failed = 0;
+    let mut retryable = None;
+    let mut rejected = None;
+
+    for batch in items.chunks(size.get()) {
+        let Err(err) = send(batch).await else {
+            continue;
+        };
+
+        failed += 1;
+
+        if output::is_rejected(&err) {
+            rejected.get_or_insert(err);
+        } else {
+            retryable.get_or_insert(err);
+        }
+    }
+
+    match retryable.or(rejected) {
+        Some(err) => Err(err.context(format!(
+            "{failed} of {} batches failed",
+            items.len().div_ceil(size.get())
+        ))),
+
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod send_batches {
+        use std::num::NonZeroUsize;
+
+        use anyhow::{
+            anyhow,
+            Error,
+        };
+        use pretty_assertions::assert_eq;
+
+        use crate::output::{
+            self,
+            http::send_batches,
+            Rejected,
+        };
+
+        /// Sends batches of one item where the item says how the batch fails.
+        async fn send(items: &[&str]) -> Result<(), Error> {
+            send_batches(items, NonZeroUsize::MIN, |batch| async move {
+                match batch[0] {
+                    "rejected" => Err(anyhow!("bad request").context(Rejected)),
+                    "retry" => Err(anyhow!("service unavailable")),
+                    _ => Ok(()),
+                }
+            })
+            .await
+        }
+
+        #[tokio::test]
+        async fn retryable_before_rejected() {
+            let err = send(&["retry", "rejected", "ok"]).await.unwrap_err();
+
+            assert!(!output::is_rejected(&err));
+            assert_eq!("2 of 3 batches failed", err.to_string());
+        }
+
+        #[tokio::test]
+        async fn only_rejected() {
+            let err = send(&["ok", "rejected", "rejected"]).await.unwrap_err();
+
+            assert!(output::is_rejected(&err));
+        }
+
+        #[tokio::test]
+        async fn all_sent() {
+            assert!(send(&["ok", "ok"]).await.is_ok());
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use std::{
+    io::Write as _,
+    num::NonZeroUsize,
+};
+
+use anyhow::{
+    anyhow,
+    bail,
+    Context,
+    Error,
+};
+use async_trait::async_trait;
+use flate2::{
+    write::GzEncoder,
+    Compression,
+};
+use prometheus::proto::MetricFamily;
+use reqwest::{
+    header,
+    Url,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    output::{
+        http,
+        Write,
+    },
+    probe::format::Format,
+};
+
+/// Writes to the v1 api if `database` is set and to the v2 api if `org` and
+/// `bucket` are set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Options {
+    /// Address of the influxdb server, for example `http://influxdb:8086`.
+    pub(crate) url: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) database: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) retention_policy: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) org: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) bucket: Option<String>,
+
+    /// API token for the v2 api. The v1 api uses `basic_auth` instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) token: Option<String>,
+
+    /// Compress the request bodies with gzip.
+    #[serde(default = "default_gzip")]
+    pub(crate) gzip: bool,
+
+    /// How many lines are sent in one request at most.
+    #[serde(default = "default_batch_size")]
+    pub(crate) batch_size: NonZeroUsize,
+
+    #[serde(default, flatten)]
+    pub(crate) http: http::Options,
+}
+
+#[derive(Debug)]
+pub(crate) struct Influx {
+    url: Url,
+    token: Option<String>,
+    gzip: bool,
+    batch_size: NonZeroUsize,
+    client: http::Client,
+}
+
+fn default_gzip() -> bool {
+    true
+}
+
+fn default_batch_size() -> NonZeroUsize {
+    NonZeroUsize::new(5000).unwrap_or(NonZeroUsize::MIN)
+}
+
+impl Influx {
+    pub(super) fn new(options: Options) -> Result<Self, Error> {
+        let mut url = Url::parse(&options.url)
+            .with_context(|| format!("can not parse url {:?}", options.url))?;
+
+        let path: &[&str] = match (&options.database, &options.org, &options.bucket) {
+            (Some(_), None, None) => &["write"],
+            (None, Some(_), Some(_)) => &["api", "v2", "write"],
+            _ => bail!(
+                "either database for the v1 api or org and bucket for the v2 api are required"
+            ),
+        };
+
+        url.path_segments_mut()
+            .map_err(|()| anyhow!("url {:?} can not be a base", options.url))?
+            .pop_if_empty()
+            .extend(path);
+
+        {
+            let mut query = url.query_pairs_mut();
+
+            if let Some(database) = &options.database {
+                query.append_pair("db", database);
+            }
+
+            if let Some(retention_policy) = &options.retention_policy {
+                query.append_pair("rp", retention_policy);
+            }
+
+            if let (Some(org), Some(bucket)) = (&options.org, &options.bucket) {
+                query.append_pair("org", org);
+                query.append_pair("bucket", bucket);
+            }
+
+            query.append_pair("precision", "ns");
+        }
+
+        Ok(Self {
+            url,
+            token: options.token,
+            gzip: options.gzip,
+            batch_size: options.batch_size,
+            client: http::Client::new(options.http)?,
+        })
+    }
+
+    fn body(&self, lines: &[&str]) -> Result<Vec<u8>, Error> {
+        let mut body = lines.join("\n");
+        body.push('\n');
+
+        if !self.gzip {
+            return Ok(body.into_bytes());
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body.as_bytes())?;
+
+        Ok(encoder.finish()?)
+    }
+}
+
+#[async_trait]
+impl Write for Influx {
+    async fn write(&mut self, families: &[MetricFamily]) -> Result<(), Error> {
+        let encoded = String::from_utf8(Format::Influx.encode(families)?)?;
+        let lines = encoded.lines().collect::<Vec<_>>();
+        let this = &*self;
+
+        http::send_batches(&lines, self.batch_size, move |batch| async move {
+            let body = this.body(batch).context("can not compress lines")?;
+
+            this.client
+                .send(|client| {
+                    let mut request = client
+                        .post(this.url.clone())
+                        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+                        .body(body.clone());
+
+                    if this.gzip {
+                        request = request.header(header::CONTENT_ENCODING, "gzip");
+                    }
+
+                    if let Some(token) = &this.token {
+                        request = request.header(header::AUTHORIZATION, format!("Token {token}"));
+                    }
+
+                    request
+                })
+                .await
+                .with_context(|| format!("can not send {} lines", batch.len()))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod write {
+        use std::{
+            io::Read,
+            net::SocketAddr,
+            num::NonZeroUsize,
+        };
+
+        use flate2::read::GzDecoder;
+        use pretty_assertions::assert_eq;
+
+        use crate::output::{
+            fixtures,
+            http,
+            influx::{
+                Influx,
+                Options,
+            },
+            Write,
+        };
+
+        fn options(addr: SocketAddr) -> Options {
+            Options {
+                url: format!("http://{addr}"),
+                database: None,
+                retention_policy: None,
+                org: None,
+                bucket: None,
+                token: None,
+                gzip: true,
+                batch_size: NonZeroUsize::MIN,
+                http: http::Options::default(),
+            }
+        }
+
+        /// Uri, authorization header and decompressed body of every request.
+        fn requests(received: &fixtures::Received) -> Vec<(String, String, String)> {
+            received
+                .requests
+                .iter()
+                .map(|request| {
+                    let mut body = String::new();
+
+                    if request.headers.contains_key("content-encoding") {
+                        GzDecoder::new(&request.body[..])
+                            .read_to_string(&mut body)
+                            .unwrap();
+                    } else {
+                        body = String::from_utf8(request.body.to_vec()).unwrap();
+                    }
+
+                    (
+                        request.uri.clone(),
+                        request.headers["authorization"]
+                            .to_str()
+                            .unwrap()
+                            .to_string(),
+                        body,
+                    )
+                })
+                .collect()
+        }
+
+        #[tokio::test]
+        async fn v1() {
+            let (addr, received) = fixtures::receiver(0);
+            let mut output = Influx::new(Options {
+                database: Some("callipe".to_string()),
+                retention_policy: Some("autogen".to_string()),
+                gzip: false,
+                http: http::Options {
+                    basic_auth: Some(http::BasicAuth {
+                        username: "callipe".to_string(),
+                        password: Some("secret".to_string()),
+                    }),
+                    ..http::Options::default()
+                },
+                ..options(addr)
+            })
+            .unwrap();
+
+            output.write(&fixtures::memory()).await.unwrap();
+
+            let expected = ["mode=free byte=1", "mode=used byte=2"]
+                .into_iter()
+                .map(|line| {
+                    (
+                        "/write?db=callipe&rp=autogen&precision=ns".to_string(),
+                        "Basic Y2FsbGlwZTpzZWNyZXQ=".to_string(),
+                        format!("system_memory,{line} 1700000000000000000\n"),
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(expected, requests(&received.lock().unwrap()));
+        }
+
+        #[tokio::test]
+        async fn v2() {
+            let (addr, received) = fixtures::receiver(0);
+            let mut output = Influx::new(Options {
+                org: Some("example".to_string()),
+                bucket: Some("callipe".to_string()),
+                token: Some("secret".to_string()),
+                batch_size: NonZeroUsize::new(10).unwrap(),
+                ..options(addr)
+            })
+            .unwrap();
+
+            output.write(&fixtures::memory()).await.unwrap();
+
+            let expected = vec![(
+                "/api/v2/write?org=example&bucket=callipe&precision=ns".to_string(),
+                "Token secret".to_string(),
+                "system_memory,mode=free byte=1 1700000000000000000\nsystem_memory,mode=used \
+                 byte=2 1700000000000000000\n"
+                    .to_string(),
+            )];
+
+            assert_eq!(expected, requests(&received.lock().unwrap()));
+        }
+
+        #[test]
+        fn missing_api() {
+            let addr = "127.0.0.1:8086".parse().unwrap();
+
+            assert!(Influx::new(options(addr)).is_err());
+        }
+    }
+}
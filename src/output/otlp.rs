@@ -0,0 +1,492 @@
+use std::collections::BTreeMap;
+
+use anyhow::{
+    anyhow,
+    Context,
+    Error,
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use prometheus::proto::MetricFamily;
+use prost::Message;
+use reqwest::{
+    header::{
+        self,
+        HeaderMap,
+        HeaderName,
+        HeaderValue,
+    },
+    Url,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tonic::{
+    codec::ProstCodec,
+    codegen::http::uri::PathAndQuery,
+    metadata::{
+        AsciiMetadataKey,
+        AsciiMetadataValue,
+        MetadataMap,
+    },
+    transport::{
+        Channel,
+        ClientTlsConfig,
+        Endpoint,
+    },
+    Code,
+};
+
+use crate::{
+    output::{
+        http::{
+            self,
+            Attempt,
+        },
+        Write,
+    },
+    probe::format::otlp::{
+        self,
+        ExportMetricsServiceRequest,
+        ExportMetricsServiceResponse,
+        Resource,
+    },
+};
+
+const EXPORT: &str = "/opentelemetry.proto.collector.metrics.v1.MetricsService/Export";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Options {
+    /// Address of the OTLP receiver, for example `http://collector:4317` for
+    /// grpc or `http://collector:4318` for http.
+    pub(crate) endpoint: String,
+
+    #[serde(default)]
+    pub(crate) protocol: Protocol,
+
+    /// Headers sent with every request, for example for authentication.
+    #[serde(default)]
+    pub(crate) headers: BTreeMap<String, String>,
+
+    /// Resource attributes in addition to `host.name`, `service.name` and
+    /// `service.version`.
+    #[serde(default)]
+    pub(crate) resource: BTreeMap<String, String>,
+
+    #[serde(default, flatten)]
+    pub(crate) http: http::Options,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Protocol {
+    /// OTLP/gRPC.
+    #[default]
+    Grpc,
+
+    /// OTLP/HTTP with protobuf bodies sent to `<endpoint>/v1/metrics`.
+    Http,
+}
+
+#[derive(Debug)]
+pub(crate) struct Otlp {
+    resource: Resource,
+    transport: Transport,
+}
+
+#[derive(Debug)]
+enum Transport {
+    Grpc {
+        channel: Channel,
+        metadata: MetadataMap,
+        options: http::Options,
+    },
+
+    Http {
+        url: Url,
+        headers: HeaderMap,
+        client: http::Client,
+    },
+}
+
+impl Otlp {
+    pub(super) fn new(options: Options) -> Result<Self, Error> {
+        let transport = match options.protocol {
+            Protocol::Grpc => {
+                let mut endpoint = Endpoint::from_shared(options.endpoint.clone())
+                    .with_context(|| format!("can not parse endpoint {:?}", options.endpoint))?
+                    .timeout(options.http.timeout);
+
+                if endpoint.uri().scheme_str() == Some("https") {
+                    endpoint = endpoint.tls_config(ClientTlsConfig::new())?;
+                }
+
+                let mut metadata = MetadataMap::new();
+
+                if let Some(authorization) = options.http.authorization()? {
+                    metadata.insert(
+                        "authorization",
+                        AsciiMetadataValue::try_from(authorization.as_bytes())?,
+                    );
+                }
+
+                for (name, value) in &options.headers {
+                    metadata.insert(
+                        name.parse::<AsciiMetadataKey>()
+                            .with_context(|| format!("invalid header name {name:?}"))?,
+                        value
+                            .parse::<AsciiMetadataValue>()
+                            .with_context(|| format!("invalid value for header {name}"))?,
+                    );
+                }
+
+                Transport::Grpc {
+                    // Connects on the first export so the receiver does not
+                    // have to be up when callipe-rs starts.
+                    channel: endpoint.connect_lazy(),
+                    metadata,
+                    options: options.http,
+                }
+            }
+
+            Protocol::Http => {
+                let mut url = Url::parse(&options.endpoint)
+                    .with_context(|| format!("can not parse endpoint {:?}", options.endpoint))?;
+
+                url.path_segments_mut()
+                    .map_err(|()| anyhow!("endpoint {:?} can not be a base", options.endpoint))?
+                    .pop_if_empty()
+                    .extend(["v1", "metrics"]);
+
+                let mut headers = HeaderMap::new();
+
+                for (name, value) in &options.headers {
+                    headers.insert(
+                        name.parse::<HeaderName>()
+                            .with_context(|| format!("invalid header name {name:?}"))?,
+                        value
+                            .parse::<HeaderValue>()
+                            .with_context(|| format!("invalid value for header {name}"))?,
+                    );
+                }
+
+                Transport::Http {
+                    url,
+                    headers,
+                    client: http::Client::new(options.http)?,
+                }
+            }
+        };
+
+        Ok(Self {
+            resource: otlp::resource(&options.resource),
+            transport,
+        })
+    }
+}
+
+#[async_trait]
+impl Write for Otlp {
+    async fn write(&mut self, families: &[MetricFamily]) -> Result<(), Error> {
+        let request = otlp::request(
+            families,
+            self.resource.clone(),
+            Utc::now().timestamp_millis(),
+            *otlp::STARTED,
+        );
+
+        match &self.transport {
+            Transport::Grpc {
+                channel,
+                metadata,
+                options,
+            } => export(channel, metadata, options, request).await,
+
+            Transport::Http {
+                url,
+                headers,
+                client,
+            } => {
+                let body = request.encode_to_vec();
+
+                client
+                    .send(|client| {
+                        client
+                            .post(url.clone())
+                            .headers(headers.clone())
+                            .header(header::CONTENT_TYPE, "application/x-protobuf")
+                            .body(body.clone())
+                    })
+                    .await
+            }
+        }
+        .context("can not export metrics")
+    }
+}
+
+async fn export(
+    channel: &Channel,
+    metadata: &MetadataMap,
+    options: &http::Options,
+    request: ExportMetricsServiceRequest,
+) -> Result<(), Error> {
+    http::retry(options, || async {
+        let mut grpc = tonic::client::Grpc::new(channel.clone());
+
+        grpc.ready()
+            .await
+            .map_err(|err| Attempt::Retry(err.into()))?;
+
+        let mut request = tonic::Request::new(request.clone());
+        *request.metadata_mut() = metadata.clone();
+
+        grpc.unary::<_, ExportMetricsServiceResponse, _>(
+            request,
+            PathAndQuery::from_static(EXPORT),
+            ProstCodec::default(),
+        )
+        .await
+        .map(|_| ())
+        .map_err(|status| {
+            let retry = matches!(
+                status.code(),
+                Code::Unavailable
+                    | Code::ResourceExhausted
+                    | Code::DeadlineExceeded
+                    | Code::Aborted
+            );
+
+            let err = Error::from(status);
+
+            if retry {
+                Attempt::Retry(err)
+            } else {
+                Attempt::Fail(err)
+            }
+        })
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    mod write {
+        use std::{
+            collections::BTreeMap,
+            future::Ready,
+            net::SocketAddr,
+            sync::{
+                Arc,
+                Mutex,
+            },
+        };
+
+        use axum::{
+            body::{
+                Body,
+                Bytes,
+            },
+            extract::State,
+            http::{
+                HeaderMap,
+                Request,
+                StatusCode,
+            },
+            response::{
+                IntoResponse,
+                Response,
+            },
+            routing::post,
+            Router,
+        };
+        use pretty_assertions::assert_eq;
+        use prost::Message;
+        use tonic::{
+            codec::ProstCodec,
+            server::{
+                Grpc,
+                UnaryService,
+            },
+            Status,
+        };
+
+        use crate::{
+            output::{
+                fixtures,
+                http,
+                otlp::{
+                    Options,
+                    Otlp,
+                    Protocol,
+                },
+                Write,
+            },
+            probe::format::otlp::{
+                ExportMetricsServiceRequest,
+                ExportMetricsServiceResponse,
+                Value,
+            },
+        };
+
+        type Received = Arc<Mutex<Vec<(HeaderMap, ExportMetricsServiceRequest)>>>;
+
+        /// Records the requests the grpc server decoded.
+        #[derive(Debug, Clone)]
+        struct Export(Received);
+
+        impl UnaryService<ExportMetricsServiceRequest> for Export {
+            type Response = ExportMetricsServiceResponse;
+            type Future = Ready<Result<tonic::Response<Self::Response>, Status>>;
+
+            fn call(
+                &mut self,
+                request: tonic::Request<ExportMetricsServiceRequest>,
+            ) -> Self::Future {
+                let headers = request.metadata().clone().into_headers();
+
+                self.0.lock().unwrap().push((headers, request.into_inner()));
+
+                std::future::ready(Ok(tonic::Response::new(ExportMetricsServiceResponse {})))
+            }
+        }
+
+        async fn grpc(State(received): State<Received>, request: Request<Body>) -> Response {
+            Grpc::new(ProstCodec::default())
+                .unary(Export(received), request)
+                .await
+                .map(axum::body::boxed)
+        }
+
+        async fn http(
+            State(received): State<Received>,
+            headers: HeaderMap,
+            body: Bytes,
+        ) -> impl IntoResponse {
+            received
+                .lock()
+                .unwrap()
+                .push((headers, ExportMetricsServiceRequest::decode(body).unwrap()));
+
+            StatusCode::OK
+        }
+
+        fn receiver(http2: bool) -> (SocketAddr, Received) {
+            let received = Received::default();
+
+            let app = Router::new()
+                .route(
+                    "/opentelemetry.proto.collector.metrics.v1.MetricsService/Export",
+                    post(grpc),
+                )
+                .route("/v1/metrics", post(http))
+                .with_state(received.clone());
+
+            (fixtures::serve(app, http2), received)
+        }
+
+        fn options(protocol: Protocol, addr: SocketAddr) -> Options {
+            Options {
+                endpoint: format!("http://{addr}"),
+                protocol,
+                headers: BTreeMap::from([("x-token".to_string(), "secret".to_string())]),
+                resource: BTreeMap::from([(
+                    "deployment.environment".to_string(),
+                    "test".to_string(),
+                )]),
+                http: http::Options::default(),
+            }
+        }
+
+        /// Returns the token, the resource attribute keys and the metric
+        /// names of every received request.
+        fn summary(received: &Received) -> Vec<(Option<String>, Vec<String>, Vec<String>)> {
+            received
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(headers, request)| {
+                    let token = headers
+                        .get("x-token")
+                        .map(|token| token.to_str().unwrap().to_string());
+
+                    let resource_metrics = &request.resource_metrics[0];
+
+                    let attributes = resource_metrics
+                        .resource
+                        .as_ref()
+                        .unwrap()
+                        .attributes
+                        .iter()
+                        .filter(|attribute| attribute.key != "host.name")
+                        .map(|attribute| match &attribute.value.as_ref().unwrap().value {
+                            Some(Value::StringValue(value)) => {
+                                format!("{}={value}", attribute.key)
+                            }
+                            None => attribute.key.clone(),
+                        })
+                        .filter(|attribute| !attribute.starts_with("service.version"))
+                        .collect();
+
+                    let metrics = resource_metrics.scope_metrics[0]
+                        .metrics
+                        .iter()
+                        .map(|metric| metric.name.clone())
+                        .collect();
+
+                    (token, attributes, metrics)
+                })
+                .collect()
+        }
+
+        fn expected() -> Vec<(Option<String>, Vec<String>, Vec<String>)> {
+            vec![(
+                Some("secret".to_string()),
+                vec![
+                    "deployment.environment=test".to_string(),
+                    "service.name=callipe-rs".to_string(),
+                ],
+                vec!["system_load_1".to_string()],
+            )]
+        }
+
+        #[tokio::test]
+        async fn grpc_export() {
+            let (addr, received) = receiver(true);
+            let mut output = Otlp::new(options(Protocol::Grpc, addr)).unwrap();
+
+            output.write(&fixtures::load(0.5)).await.unwrap();
+
+            assert_eq!(expected(), summary(&received));
+        }
+
+        #[tokio::test]
+        async fn http_export() {
+            let (addr, received) = receiver(false);
+            let mut output = Otlp::new(options(Protocol::Http, addr)).unwrap();
+
+            output.write(&fixtures::load(0.5)).await.unwrap();
+
+            assert_eq!(expected(), summary(&received));
+        }
+
+        #[tokio::test]
+        async fn grpc_bearer_token() {
+            let (addr, received) = receiver(true);
+
+            let mut options = options(Protocol::Grpc, addr);
+            options.http.bearer_token = Some("secret".to_string());
+
+            let mut output = Otlp::new(options).unwrap();
+
+            output.write(&fixtures::load(0.5)).await.unwrap();
+
+            let authorization = received.lock().unwrap()[0]
+                .0
+                .get("authorization")
+                .map(|value| value.to_str().unwrap().to_string());
+
+            assert_eq!(Some("Bearer secret".to_string()), authorization);
+        }
+    }
+}
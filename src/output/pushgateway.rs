@@ -0,0 +1,248 @@
+use std::collections::BTreeMap;
+
+use anyhow::{
+    anyhow,
+    bail,
+    Context,
+    Error,
+};
+use async_trait::async_trait;
+use base64::{
+    engine::general_purpose::URL_SAFE,
+    Engine,
+};
+use prometheus::proto::MetricFamily;
+use reqwest::{
+    header,
+    Method,
+    Url,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    output::{
+        self,
+        http,
+        Write,
+    },
+    probe::format::Format,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Options {
+    /// Address of the pushgateway, for example `http://pushgateway:9091`.
+    pub(crate) url: String,
+
+    pub(crate) job: String,
+
+    /// Labels that together with `job` make up the grouping key.
+    #[serde(default)]
+    pub(crate) grouping: BTreeMap<String, String>,
+
+    #[serde(default)]
+    pub(crate) method: PushMethod,
+
+    #[serde(default, flatten)]
+    pub(crate) http: http::Options,
+}
+
+/// How the pushgateway treats metrics of the group that were pushed before.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum PushMethod {
+    /// Replace all metrics of the group.
+    #[default]
+    Put,
+
+    /// Only replace metrics with the same name.
+    Post,
+}
+
+#[derive(Debug)]
+pub(crate) struct Pushgateway {
+    url: Url,
+    method: Method,
+    client: http::Client,
+}
+
+impl Pushgateway {
+    pub(super) fn new(options: Options) -> Result<Self, Error> {
+        let mut url = Url::parse(&options.url)
+            .with_context(|| format!("can not parse url {:?}", options.url))?;
+
+        if options.job.is_empty() {
+            bail!("job can not be empty")
+        }
+
+        {
+            let mut segments = url
+                .path_segments_mut()
+                .map_err(|()| anyhow!("url {:?} can not be a base", options.url))?;
+
+            segments.pop_if_empty().push("metrics");
+
+            for (name, value) in std::iter::once(("job", &options.job)).chain(
+                options
+                    .grouping
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value)),
+            ) {
+                // Values with slashes would be split into more path segments
+                // and empty values would be dropped.
+                if value.is_empty() || value.contains('/') {
+                    segments.extend([format!("{name}@base64"), URL_SAFE.encode(value)]);
+                } else {
+                    segments.extend([name, value.as_str()]);
+                }
+            }
+        }
+
+        Ok(Self {
+            url,
+            method: match options.method {
+                PushMethod::Put => Method::PUT,
+                PushMethod::Post => Method::POST,
+            },
+            client: http::Client::new(options.http)?,
+        })
+    }
+}
+
+#[async_trait]
+impl Write for Pushgateway {
+    async fn write(&mut self, families: &[MetricFamily]) -> Result<(), Error> {
+        // The pushgateway rejects metrics with timestamps and more than one
+        // sample of a series.
+        let mut families = families.to_vec();
+        output::latest(&mut families);
+
+        for family in &mut families {
+            for metric in family.mut_metric().iter_mut() {
+                metric.clear_timestamp_ms();
+            }
+        }
+
+        let body = Format::Prometheus.encode(&families)?;
+
+        self.client
+            .send(|client| {
+                client
+                    .request(self.method.clone(), self.url.clone())
+                    .header(header::CONTENT_TYPE, Format::Prometheus.content_type())
+                    .body(body.clone())
+            })
+            .await
+            .context("can not push metrics")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod write {
+        use std::collections::BTreeMap;
+
+        use axum::http::Method;
+        use pretty_assertions::assert_eq;
+        use prometheus::{
+            register_gauge_with_registry,
+            Registry,
+        };
+
+        use crate::output::{
+            fixtures::{
+                self,
+                Received,
+            },
+            http,
+            pushgateway::{
+                Options,
+                PushMethod,
+                Pushgateway,
+            },
+            Write,
+        };
+
+        fn requests(received: &Received) -> Vec<(Method, String, String)> {
+            received
+                .requests
+                .iter()
+                .map(|request| {
+                    (
+                        request.method.clone(),
+                        request.uri.clone(),
+                        String::from_utf8(request.body.to_vec()).unwrap(),
+                    )
+                })
+                .collect()
+        }
+
+        #[tokio::test]
+        async fn two_collections() {
+            let (addr, received) = fixtures::receiver(0);
+
+            let mut output = Pushgateway::new(Options {
+                url: format!("http://{addr}"),
+                job: "callipe".to_string(),
+                grouping: BTreeMap::new(),
+                method: PushMethod::Put,
+                http: http::Options::default(),
+            })
+            .unwrap();
+
+            // Both collections of the input within one flush.
+            let mut families = fixtures::load(0.5);
+            let newer = fixtures::load(0.25)[0].get_metric()[0].clone();
+            families[0].mut_metric().push(newer);
+
+            output.write(&families).await.unwrap();
+
+            let expected = vec![(
+                Method::PUT,
+                "/metrics/job/callipe".to_string(),
+                "# HELP system_load_1 load\n# TYPE system_load_1 gauge\nsystem_load_1 0.25\n"
+                    .to_string(),
+            )];
+
+            assert_eq!(expected, requests(&received.lock().unwrap()));
+        }
+
+        #[tokio::test]
+        async fn grouping_key() {
+            let (addr, received) = fixtures::receiver(0);
+
+            let mut output = Pushgateway::new(Options {
+                url: format!("http://{addr}/"),
+                job: "ci".to_string(),
+                grouping: BTreeMap::from([
+                    ("instance".to_string(), "runner-1".to_string()),
+                    ("path".to_string(), "/builds".to_string()),
+                ]),
+                method: PushMethod::Post,
+                http: http::Options::default(),
+            })
+            .unwrap();
+
+            let registry = Registry::new();
+
+            register_gauge_with_registry!("info", "info", registry)
+                .unwrap()
+                .set(1.0);
+
+            let mut families = registry.gather();
+            families[0].mut_metric()[0].set_timestamp_ms(fixtures::TIMESTAMP);
+
+            output.write(&families).await.unwrap();
+
+            let expected = vec![(
+                Method::POST,
+                "/metrics/job/ci/instance/runner-1/path@base64/L2J1aWxkcw==".to_string(),
+                "# HELP info info\n# TYPE info gauge\ninfo 1\n".to_string(),
+            )];
+
+            assert_eq!(expected, requests(&received.lock().unwrap()));
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use std::{
+    collections::BTreeMap,
+    num::NonZeroUsize,
+};
+
+use anyhow::{
+    Context,
+    Error,
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use prometheus::proto::MetricFamily;
+use prost::Message;
+use reqwest::{
+    header,
+    Url,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::output::{
+    self,
+    http,
+    Write,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Options {
+    /// Remote write endpoint of the receiver, for example
+    /// `http://prometheus:9090/api/v1/write`.
+    pub(crate) url: String,
+
+    /// Labels added to every sample. Labels of the metrics take precedence.
+    #[serde(default)]
+    pub(crate) labels: BTreeMap<String, String>,
+
+    /// How many samples are sent in one request at most.
+    #[serde(default = "default_batch_size")]
+    pub(crate) batch_size: NonZeroUsize,
+
+    #[serde(default, flatten)]
+    pub(crate) http: http::Options,
+}
+
+#[derive(Debug)]
+pub(crate) struct RemoteWrite {
+    url: Url,
+    labels: BTreeMap<String, String>,
+    batch_size: NonZeroUsize,
+    client: http::Client,
+}
+
+// Messages of the remote write protocol as defined in prompb/remote.proto and
+// prompb/types.proto of prometheus.
+
+#[derive(Clone, PartialEq, Message)]
+struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TimeSeries {
+    #[prost(message, repeated, tag = "1")]
+    labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Label {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Sample {
+    #[prost(double, tag = "1")]
+    value: f64,
+    #[prost(int64, tag = "2")]
+    timestamp: i64,
+}
+
+fn default_batch_size() -> NonZeroUsize {
+    NonZeroUsize::new(2000).unwrap_or(NonZeroUsize::MIN)
+}
+
+impl RemoteWrite {
+    pub(super) fn new(options: Options) -> Result<Self, Error> {
+        let url = Url::parse(&options.url)
+            .with_context(|| format!("can not parse url {:?}", options.url))?;
+
+        Ok(Self {
+            url,
+            labels: options.labels,
+            batch_size: options.batch_size,
+            client: http::Client::new(options.http)?,
+        })
+    }
+}
+
+#[async_trait]
+impl Write for RemoteWrite {
+    async fn write(&mut self, families: &[MetricFamily]) -> Result<(), Error> {
+        let series = series(families, &self.labels, Utc::now().timestamp_millis());
+        let this = &*self;
+
+        http::send_batches(&series, self.batch_size, move |batch| async move {
+            let request = WriteRequest {
+                timeseries: batch.to_vec(),
+            };
+
+            let body = snap::raw::Encoder::new()
+                .compress_vec(&request.encode_to_vec())
+                .context("can not compress write request")?;
+
+            this.client
+                .send(|client| {
+                    client
+                        .post(this.url.clone())
+                        .header(header::CONTENT_TYPE, "application/x-protobuf")
+                        .header(header::CONTENT_ENCODING, "snappy")
+                        .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+                        .body(body.clone())
+                })
+                .await
+                .with_context(|| format!("can not send {} samples", batch.len()))
+        })
+        .await
+    }
+}
+
+/// Turns every sample of `families` into its own series with the labels
+/// sorted by name as the protocol requires.
+fn series(
+    families: &[MetricFamily],
+    labels: &BTreeMap<String, String>,
+    timestamp: i64,
+) -> Vec<TimeSeries> {
+    output::samples(families, timestamp)
+        .into_iter()
+        .map(|sample| {
+            let mut sorted = labels.clone();
+            sorted.extend(sample.labels);
+            sorted.insert("__name__".to_string(), sample.name);
+
+            TimeSeries {
+                labels: sorted
+                    .into_iter()
+                    .map(|(name, value)| Label { name, value })
+                    .collect(),
+                samples: vec![Sample {
+                    value: sample.value,
+                    timestamp: sample.timestamp,
+                }],
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    mod write {
+        use std::{
+            collections::BTreeMap,
+            net::SocketAddr,
+            num::NonZeroUsize,
+            time::Duration,
+        };
+
+        use pretty_assertions::assert_eq;
+        use prost::Message;
+
+        use crate::output::{
+            fixtures::{
+                self,
+                TIMESTAMP,
+            },
+            http,
+            remote_write::{
+                Options,
+                RemoteWrite,
+                WriteRequest,
+            },
+            Write,
+        };
+
+        fn options(addr: SocketAddr) -> Options {
+            Options {
+                url: format!("http://{addr}/api/v1/write"),
+                labels: BTreeMap::from([
+                    ("env".to_string(), "test".to_string()),
+                    ("mode".to_string(), "ignored".to_string()),
+                ]),
+                batch_size: NonZeroUsize::MIN,
+                http: http::Options {
+                    backoff: Duration::from_millis(1),
+                    bearer_token: Some("secret".to_string()),
+                    ..http::Options::default()
+                },
+            }
+        }
+
+        #[tokio::test]
+        async fn batches() {
+            let (addr, received) = fixtures::receiver(0);
+            let mut output = RemoteWrite::new(options(addr)).unwrap();
+
+            output.write(&fixtures::memory()).await.unwrap();
+
+            let received = received.lock().unwrap();
+
+            let series = received
+                .requests
+                .iter()
+                .flat_map(|request| {
+                    let body = snap::raw::Decoder::new()
+                        .decompress_vec(&request.body)
+                        .unwrap();
+
+                    WriteRequest::decode(body.as_slice()).unwrap().timeseries
+                })
+                .map(|series| {
+                    let labels = series
+                        .labels
+                        .iter()
+                        .map(|label| format!("{}={}", label.name, label.value))
+                        .collect::<Vec<_>>()
+                        .join(",");
+
+                    (labels, series.samples[0].value, series.samples[0].timestamp)
+                })
+                .collect::<Vec<_>>();
+
+            let authorization = received
+                .requests
+                .iter()
+                .map(|request| request.headers["authorization"].to_str().unwrap())
+                .collect::<Vec<_>>();
+
+            assert_eq!(
+                vec![
+                    (
+                        "__name__=system_memory_byte,env=test,mode=free".to_string(),
+                        1.0,
+                        TIMESTAMP
+                    ),
+                    (
+                        "__name__=system_memory_byte,env=test,mode=used".to_string(),
+                        2.0,
+                        TIMESTAMP
+                    ),
+                ],
+                series
+            );
+            assert_eq!(vec!["Bearer secret"; 2], authorization);
+        }
+
+        #[tokio::test]
+        async fn retries() {
+            let (addr, received) = fixtures::receiver(2);
+            let mut output = RemoteWrite::new(Options {
+                batch_size: NonZeroUsize::new(10).unwrap(),
+                ..options(addr)
+            })
+            .unwrap();
+
+            output.write(&fixtures::memory()).await.unwrap();
+
+            assert_eq!(1, received.lock().unwrap().requests.len());
+        }
+
+        #[tokio::test]
+        async fn gives_up() {
+            let (addr, received) = fixtures::receiver(10);
+            let mut output = RemoteWrite::new(Options {
+                batch_size: NonZeroUsize::new(10).unwrap(),
+                ..options(addr)
+            })
+            .unwrap();
+
+            assert!(output.write(&fixtures::memory()).await.is_err());
+            // The first attempt and three retries each used up a failure.
+            assert_eq!(6, received.lock().unwrap().failures);
+        }
+    }
+}
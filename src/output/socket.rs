@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use anyhow::{
+    Context,
+    Error,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{
+        TcpStream,
+        UdpSocket,
+    },
+    time,
+};
+
+/// Datagrams are filled with lines up to this size so they fit into the
+/// usual ethernet MTU without being fragmented.
+const MAX_DATAGRAM: usize = 1432;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Protocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+/// Sends `lines` to `address` over a new connection. Over udp as many lines
+/// as fit are sent in one datagram.
+pub(super) async fn send(
+    protocol: Protocol,
+    address: &str,
+    lines: &[String],
+    timeout: Duration,
+) -> Result<(), Error> {
+    time::timeout(timeout, async {
+        match protocol {
+            Protocol::Tcp => {
+                let mut stream = TcpStream::connect(address)
+                    .await
+                    .with_context(|| format!("can not connect to {address}"))?;
+
+                stream.write_all(lines.concat().as_bytes()).await?;
+                stream.shutdown().await?;
+            }
+
+            Protocol::Udp => {
+                let target = tokio::net::lookup_host(address)
+                    .await?
+                    .next()
+                    .with_context(|| format!("can not resolve {address}"))?;
+
+                let bind = if target.is_ipv4() {
+                    "0.0.0.0:0"
+                } else {
+                    "[::]:0"
+                };
+
+                let socket = UdpSocket::bind(bind).await?;
+                socket.connect(target).await?;
+
+                for datagram in datagrams(lines) {
+                    socket.send(datagram.as_bytes()).await?;
+                }
+            }
+        }
+
+        Ok(())
+    })
+    .await
+    .with_context(|| format!("sending to {address} timed out"))?
+}
+
+/// Joins `lines` into datagrams of at most [`MAX_DATAGRAM`] bytes. Lines that
+/// are longer are sent on their own.
+fn datagrams(lines: &[String]) -> Vec<String> {
+    let mut datagrams = Vec::new();
+    let mut current = String::new();
+
+    for line in lines {
+        if !current.is_empty() && current.len() + line.len() > MAX_DATAGRAM {
+            datagrams.push(std::mem::take(&mut current));
+        }
+
+        current.push_str(line);
+    }
+
+    if !current.is_empty() {
+        datagrams.push(current);
+    }
+
+    datagrams
+}
+
+#[cfg(test)]
+mod tests {
+    mod datagrams {
+        use pretty_assertions::assert_eq;
+
+        use crate::output::socket::{
+            datagrams,
+            MAX_DATAGRAM,
+        };
+
+        #[test]
+        fn split() {
+            let line = format!("{}\n", "a".repeat(MAX_DATAGRAM / 2 - 1));
+            let lines = vec![line.clone(), line.clone(), line.clone()];
+
+            let got = datagrams(&lines);
+
+            assert_eq!(vec![format!("{line}{line}"), line], got);
+        }
+    }
+}
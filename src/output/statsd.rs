@@ -0,0 +1,225 @@
+use std::{
+    collections::HashMap,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use anyhow::Error;
+use async_trait::async_trait;
+use prometheus::proto::{
+    MetricFamily,
+    MetricType,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::output::{
+    self,
+    graphite::Template,
+    socket::{
+        self,
+        Protocol,
+    },
+    Write,
+};
+
+/// Counters that were not written for this long are forgotten so series that
+/// went away do not pile up.
+const STALE_COUNTER: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Options {
+    /// Address of the statsd server, for example `statsd:8125`.
+    pub(crate) address: String,
+
+    /// How the name of a sample is built, the same as for the graphite output.
+    #[serde(default = "default_template")]
+    pub(crate) template: String,
+}
+
+#[derive(Debug)]
+pub(crate) struct Statsd {
+    address: String,
+    template: Template,
+
+    /// Last value of every counter by name and labels to send the increase
+    /// since the last flush.
+    counters: HashMap<(String, Vec<(String, String)>), Counter>,
+}
+
+#[derive(Debug)]
+struct Counter {
+    value: f64,
+    written: Instant,
+}
+
+fn default_template() -> String {
+    "{name}.{labels}".to_string()
+}
+
+impl Statsd {
+    pub(super) fn new(options: Options) -> Result<Self, Error> {
+        Ok(Self {
+            address: options.address,
+            template: options.template.parse()?,
+            counters: HashMap::new(),
+        })
+    }
+}
+
+#[async_trait]
+impl Write for Statsd {
+    async fn write(&mut self, families: &[MetricFamily]) -> Result<(), Error> {
+        let now = Instant::now();
+        let samples = self.template.paths(output::samples(families, 0));
+        let mut lines = Vec::new();
+
+        for (name, sample) in samples {
+            if sample.kind == MetricType::COUNTER {
+                let counter = Counter {
+                    value: sample.value,
+                    written: now,
+                };
+
+                // The first value of a counter is only remembered as nothing
+                // is known about the increase before it.
+                let Some(previous) = self.counters.insert((sample.name, sample.labels), counter)
+                else {
+                    continue;
+                };
+
+                // Counters that went down were reset.
+                let increase = if sample.value < previous.value {
+                    sample.value
+                } else {
+                    sample.value - previous.value
+                };
+
+                lines.push(format!("{name}:{increase}|c\n"));
+            } else {
+                // A leading sign changes the gauge by the value instead of
+                // setting it, so negative values have to be set from zero.
+                if sample.value.is_sign_negative() {
+                    lines.push(format!("{name}:0|g\n"));
+                }
+
+                lines.push(format!("{name}:{}|g\n", sample.value));
+            }
+        }
+
+        self.counters
+            .retain(|_, counter| now.duration_since(counter.written) < STALE_COUNTER);
+
+        socket::send(
+            Protocol::Udp,
+            &self.address,
+            &lines,
+            Duration::from_secs(10),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod write {
+        use pretty_assertions::assert_eq;
+        use prometheus::{
+            register_counter_with_registry,
+            register_gauge_vec_with_registry,
+            register_gauge_with_registry,
+            Counter,
+            Registry,
+        };
+        use tokio::net::UdpSocket;
+
+        use crate::output::{
+            statsd::{
+                Options,
+                Statsd,
+            },
+            Write,
+        };
+
+        #[tokio::test]
+        async fn counters_and_gauges() {
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+            let mut output = Statsd::new(Options {
+                address: socket.local_addr().unwrap().to_string(),
+                template: "callipe.{name}".to_string(),
+            })
+            .unwrap();
+
+            let registry = Registry::new();
+
+            let counter: Counter =
+                register_counter_with_registry!("packets_total", "packets", registry).unwrap();
+
+            register_gauge_with_registry!("temperature_celsius", "temperature", registry)
+                .unwrap()
+                .set(-4.5);
+
+            let mut buffer = [0; 1500];
+            let mut received = Vec::new();
+
+            for increase in [10.0, 2.5] {
+                counter.inc_by(increase);
+                output.write(&registry.gather()).await.unwrap();
+
+                let length = socket.recv(&mut buffer).await.unwrap();
+                received.push(String::from_utf8_lossy(&buffer[..length]).to_string());
+            }
+
+            let expected = vec![
+                "callipe.temperature_celsius:0|g\ncallipe.temperature_celsius:-4.5|g\n",
+                "callipe.packets_total:2.5|c\ncallipe.temperature_celsius:0|g\ncallipe.\
+                 temperature_celsius:-4.5|g\n",
+            ];
+
+            assert_eq!(expected, received);
+        }
+
+        #[tokio::test]
+        async fn collision() {
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+            let mut output = Statsd::new(Options {
+                address: socket.local_addr().unwrap().to_string(),
+                template: "callipe.{name}".to_string(),
+            })
+            .unwrap();
+
+            let registry = Registry::new();
+
+            let gauge = register_gauge_vec_with_registry!(
+                "system_network_up",
+                "interface is up",
+                &["interface"],
+                registry
+            )
+            .unwrap();
+
+            gauge.with_label_values(&["eth0"]).set(1.0);
+            gauge.with_label_values(&["eth1"]).set(0.0);
+
+            register_gauge_with_registry!("temperature_celsius", "temperature", registry)
+                .unwrap()
+                .set(21.0);
+
+            output.write(&registry.gather()).await.unwrap();
+
+            let mut buffer = [0; 1500];
+            let length = socket.recv(&mut buffer).await.unwrap();
+
+            assert_eq!(
+                "callipe.temperature_celsius:21|g\n",
+                String::from_utf8_lossy(&buffer[..length])
+            );
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use anyhow::Error;
+use async_trait::async_trait;
+use prometheus::proto::MetricFamily;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    output::{
+        self,
+        Write,
+    },
+    probe::format::Format,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Options {
+    pub(crate) format: Format,
+}
+
+#[derive(Debug)]
+pub(crate) struct Stdout {
+    options: Options,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            format: Format::Influx,
+        }
+    }
+}
+
+impl Stdout {
+    pub(super) fn new(options: Options) -> Self {
+        Self { options }
+    }
+}
+
+#[async_trait]
+impl Write for Stdout {
+    async fn write(&mut self, families: &[MetricFamily]) -> Result<(), Error> {
+        let buffer = output::encode(self.options.format, families)?;
+
+        let mut stdout = tokio::io::stdout();
+        stdout.write_all(&buffer).await?;
+        stdout.flush().await?;
+
+        Ok(())
+    }
+}
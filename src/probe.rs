@@ -1,3 +1,460 @@
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+};
+
+use anyhow::{
+    bail,
+    Error,
+};
+use axum::{
+    extract::Query,
+    http::{
+        header,
+        StatusCode,
+    },
+    response::{
+        IntoResponse,
+        Response,
+    },
+    routing::get,
+    Extension,
+    Router,
+};
+use prometheus::{
+    proto::{
+        LabelPair,
+        MetricFamily,
+    },
+    register_int_gauge_vec_with_registry,
+    register_int_gauge_with_registry,
+    Registry,
+};
+use serde::de::DeserializeOwned;
+
+pub(crate) mod filter;
+pub(crate) mod format;
 pub(crate) mod info;
 pub(crate) mod ping;
+pub(crate) mod select;
 pub(crate) mod system;
+
+use filter::Filter;
+use format::Format;
+use select::Names;
+
+/// A source of metrics that is served on its own route and can be composed
+/// with other probes.
+pub(crate) trait Probe: 'static {
+    /// Name of the route and the `probe` label of `probe_error`.
+    const NAME: &'static str;
+
+    /// Names of the labels of the metrics of the probe, which the labels
+    /// from the config must not collide with.
+    const LABELS: &'static [&'static str];
+
+    /// Options from the config file.
+    type Options: Clone + Send + Sync + 'static;
+
+    /// Query parameters of a single request. The default parameters are used
+    /// when the probe is composed with others.
+    type Params: DeserializeOwned + Default + Send + 'static;
+
+    /// Rejects parameters that can not be served before anything is collected.
+    fn validate(_params: &Self::Params) -> Result<(), ProbeError> {
+        Ok(())
+    }
+
+    /// Collects the metrics of the probe into `registry`.
+    fn collect(
+        registry: &Registry,
+        options: &Self::Options,
+        params: Self::Params,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Parameters and labels to collect with in agent mode. Every entry is
+    /// collected into its own registry with the labels added to all metrics.
+    fn scheduled(_options: &Self::Options) -> Vec<(Self::Params, HashMap<String, String>)> {
+        vec![(Self::Params::default(), HashMap::new())]
+    }
+}
+
+/// Labels from the config that are added to every metric of an input.
+pub(crate) type Labels = BTreeMap<String, String>;
+
+pub(crate) type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Object safe view of a [`Probe`] with its options so probes of different
+/// types can be stored and run together.
+pub(crate) trait AnyProbe: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Collects with the default parameters into `collector`.
+    fn collect<'a>(&'a self, collector: &'a mut Collector) -> BoxFuture<'a, ()>;
+
+    /// Collects everything from [`Probe::scheduled`] and returns the metrics.
+    /// Failures are logged and skipped.
+    fn gather(&self) -> BoxFuture<'_, Vec<MetricFamily>>;
+}
+
+struct Configured<P: Probe> {
+    options: P::Options,
+    labels: Labels,
+
+    /// Names of the metric families that are returned.
+    metrics: Filter,
+}
+
+/// Errors a probe handler can respond with. Failures while collecting metrics
+/// are not errors of the request, they are reported through [`Collector`].
+#[derive(Debug)]
+pub(crate) enum ProbeError {
+    /// The request parameters are invalid.
+    BadRequest(String),
+
+    /// The collected metrics could not be encoded.
+    Encode(Error),
+}
+
+/// Metrics of a single request that keeps track of which probes failed so
+/// the response still contains everything that could be collected.
+#[derive(Debug)]
+pub(crate) struct Collector {
+    families: Vec<MetricFamily>,
+    failed: Vec<&'static str>,
+
+    /// Metric families the request asked for.
+    names: Names,
+}
+
+/// Serves `P` with `options` on `/<name>` of `router`. Only the metric
+/// families that pass `metrics` are returned and `labels` are added to them.
+pub(crate) fn route<P: Probe>(
+    router: Router,
+    options: P::Options,
+    labels: Labels,
+    metrics: Filter,
+) -> Router {
+    let probe = Configured::<P> {
+        options,
+        labels,
+        metrics,
+    };
+
+    router.route(
+        &format!("/{}", P::NAME),
+        get(handler::<P>).layer(Extension(Arc::new(probe))),
+    )
+}
+
+async fn handler<P: Probe>(
+    Extension(probe): Extension<Arc<Configured<P>>>,
+    Query(params): Query<P::Params>,
+    names: Names,
+    format: Format,
+) -> Result<Response, ProbeError> {
+    P::validate(&params)?;
+
+    let mut collector = Collector::new(names);
+    collector.collect(probe.as_ref(), params).await;
+
+    collector.finish(format)
+}
+
+/// Erases the type of `P` so it can be composed with other probes. Only the
+/// metric families that pass `metrics` are returned and `labels` are added to
+/// them.
+pub(crate) fn configured<P: Probe>(
+    options: P::Options,
+    labels: Labels,
+    metrics: Filter,
+) -> Arc<dyn AnyProbe> {
+    Arc::new(Configured::<P> {
+        options,
+        labels,
+        metrics,
+    })
+}
+
+impl<P: Probe> Configured<P> {
+    /// Drops the metric families that do not pass the filter and labels the
+    /// rest.
+    fn shape(&self, families: &mut Vec<MetricFamily>) -> Result<(), Error> {
+        families.retain(|family| self.metrics.is_match(family.get_name()));
+        label(families, &self.labels)
+    }
+}
+
+impl<P: Probe> AnyProbe for Configured<P> {
+    fn name(&self) -> &'static str {
+        P::NAME
+    }
+
+    fn collect<'a>(&'a self, collector: &'a mut Collector) -> BoxFuture<'a, ()> {
+        Box::pin(collector.collect(self, P::Params::default()))
+    }
+
+    fn gather(&self) -> BoxFuture<'_, Vec<MetricFamily>> {
+        Box::pin(async move {
+            let mut families = Vec::new();
+
+            for (params, labels) in P::scheduled(&self.options) {
+                let registry = match Registry::new_custom(None, Some(labels)) {
+                    Ok(registry) => registry,
+                    Err(err) => {
+                        eprintln!("probe {} failed: {err:#}", P::NAME);
+                        continue;
+                    }
+                };
+
+                if let Err(err) = P::collect(&registry, &self.options, params).await {
+                    eprintln!("probe {} failed: {err:#}", P::NAME);
+                }
+
+                let mut collected = registry.gather();
+
+                match self.shape(&mut collected) {
+                    Ok(()) => families.extend(collected),
+                    Err(err) => eprintln!("probe {} failed: {err:#}", P::NAME),
+                }
+            }
+
+            families
+        })
+    }
+}
+
+impl IntoResponse for ProbeError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::BadRequest(message) => (StatusCode::BAD_REQUEST, message).into_response(),
+            Self::Encode(err) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("can not encode metrics: {err:#}"),
+            )
+                .into_response(),
+        }
+    }
+}
+
+impl Collector {
+    pub(crate) fn new(names: Names) -> Self {
+        Self {
+            families: Vec::new(),
+            failed: Vec::new(),
+            names,
+        }
+    }
+
+    /// Runs `probe` with `params`. Errors are logged and reported with
+    /// `probe_error` instead of failing the request. Metrics collected before
+    /// the error are kept.
+    async fn collect<P: Probe>(&mut self, probe: &Configured<P>, params: P::Params) {
+        let registry = Registry::new();
+        let result = P::collect(&registry, &probe.options, params).await;
+
+        let mut families = registry.gather();
+        families.retain(|family| self.names.is_match(family.get_name()));
+
+        let shaped = probe.shape(&mut families);
+
+        if shaped.is_ok() {
+            self.families.extend(families);
+        }
+
+        if let Err(err) = result.and(shaped) {
+            eprintln!("probe {} failed: {err:#}", P::NAME);
+            self.failed.push(P::NAME);
+        }
+    }
+
+    /// Adds `probe_success` and `probe_error` and encodes all metrics in
+    /// `format`.
+    pub(crate) fn finish(self, format: Format) -> Result<Response, ProbeError> {
+        let registry = Registry::new();
+
+        register_int_gauge_with_registry!(
+            "probe_success",
+            "if all probes of the request succeeded",
+            registry
+        )
+        .map_err(|err| ProbeError::Encode(err.into()))?
+        .set(self.failed.is_empty().into());
+
+        let errors = register_int_gauge_vec_with_registry!(
+            "probe_error",
+            "if the probe failed to collect its metrics",
+            &["probe"],
+            registry
+        )
+        .map_err(|err| ProbeError::Encode(err.into()))?;
+
+        for probe in &self.failed {
+            errors.with_label_values(&[probe]).set(1);
+        }
+
+        let mut metric_families = self.families;
+        metric_families.extend(registry.gather());
+        metric_families.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+
+        let buffer = format
+            .encode(&metric_families)
+            .map_err(ProbeError::Encode)?;
+
+        Ok(([(header::CONTENT_TYPE, format.content_type())], buffer).into_response())
+    }
+}
+
+/// Adds `labels` to every metric of `families`. Fails if a metric already has
+/// a label with one of the names.
+fn label(families: &mut [MetricFamily], labels: &Labels) -> Result<(), Error> {
+    if labels.is_empty() {
+        return Ok(());
+    }
+
+    for family in families {
+        let name = family.get_name().to_string();
+
+        for metric in family.mut_metric().iter_mut() {
+            let mut pairs = metric.take_label().into_vec();
+
+            for (label, value) in labels {
+                if pairs.iter().any(|pair| pair.get_name() == label) {
+                    bail!("configured label {label} collides with a label of {name}")
+                }
+
+                let mut pair = LabelPair::new();
+                pair.set_name(label.clone());
+                pair.set_value(value.clone());
+                pairs.push(pair);
+            }
+
+            pairs.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+            metric.set_label(pairs.into());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    mod label {
+        use pretty_assertions::assert_eq;
+        use prometheus::{
+            register_int_gauge_vec_with_registry,
+            Registry,
+        };
+
+        use crate::probe::{
+            label,
+            Labels,
+        };
+
+        fn collect() -> Vec<prometheus::proto::MetricFamily> {
+            let registry = Registry::new();
+
+            register_int_gauge_vec_with_registry!(
+                "system_memory_platform_byte",
+                "memory",
+                &["os", "name"],
+                registry
+            )
+            .unwrap()
+            .with_label_values(&["linux", "active"])
+            .set(1);
+
+            registry.gather()
+        }
+
+        #[test]
+        fn added() {
+            let mut families = collect();
+
+            label(
+                &mut families,
+                &Labels::from([("env".to_string(), "production".to_string())]),
+            )
+            .unwrap();
+
+            let names = families[0].get_metric()[0]
+                .get_label()
+                .iter()
+                .map(|pair| format!("{}={}", pair.get_name(), pair.get_value()))
+                .collect::<Vec<_>>();
+
+            assert_eq!(vec!["env=production", "name=active", "os=linux"], names);
+        }
+
+        #[test]
+        fn collision() {
+            let mut families = collect();
+
+            let err = label(
+                &mut families,
+                &Labels::from([("os".to_string(), "debian".to_string())]),
+            )
+            .unwrap_err();
+
+            assert_eq!(
+                "configured label os collides with a label of system_memory_platform_byte",
+                err.to_string()
+            );
+        }
+    }
+
+    mod shape {
+        use pretty_assertions::assert_eq;
+        use prometheus::{
+            proto::MetricFamily,
+            register_gauge_with_registry,
+            Registry,
+        };
+
+        use crate::probe::{
+            filter::{
+                Filter,
+                Pattern,
+            },
+            system::load::{
+                self,
+                Load,
+            },
+            Configured,
+            Labels,
+        };
+
+        #[test]
+        fn filtered() {
+            let registry = Registry::new();
+
+            for name in ["system_load_1", "system_load_5", "system_load_15"] {
+                register_gauge_with_registry!(name, "load", registry).unwrap();
+            }
+
+            let probe = Configured::<Load> {
+                options: load::Options::default(),
+                labels: Labels::new(),
+                metrics: Filter {
+                    include: vec![Pattern::new("system_load_1.*").unwrap()],
+                    exclude: vec![Pattern::new("system_load_15").unwrap()],
+                },
+            };
+
+            let mut families = registry.gather();
+            probe.shape(&mut families).unwrap();
+
+            let names = families
+                .iter()
+                .map(MetricFamily::get_name)
+                .collect::<Vec<_>>();
+
+            assert_eq!(vec!["system_load_1"], names);
+        }
+    }
+}
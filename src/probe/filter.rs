@@ -0,0 +1,144 @@
+use std::fmt;
+
+use regex::Regex;
+use serde::{
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer,
+};
+
+/// Include and exclude lists of patterns. A value passes the filter if it
+/// matches any include pattern (or there are none) and no exclude pattern.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Filter {
+    pub(crate) include: Vec<Pattern>,
+    pub(crate) exclude: Vec<Pattern>,
+}
+
+/// Regular expression that has to match the whole value. Patterns starting
+/// with `glob:` are globs instead where `*` matches any characters and `?` a
+/// single character.
+#[derive(Debug, Clone)]
+pub(crate) struct Pattern {
+    source: String,
+    regex: Regex,
+}
+
+impl Filter {
+    pub(crate) fn exclude<I, S>(patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self {
+            include: Vec::new(),
+            exclude: patterns
+                .into_iter()
+                .map(|pattern| Pattern::new(pattern.as_ref()).expect("invalid builtin pattern"))
+                .collect(),
+        }
+    }
+
+    pub(crate) fn is_match(&self, value: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|pattern| pattern.is_match(value)))
+            && !self.exclude.iter().any(|pattern| pattern.is_match(value))
+    }
+}
+
+impl Pattern {
+    pub(crate) fn new(source: &str) -> Result<Self, regex::Error> {
+        let regex = match source.strip_prefix("glob:") {
+            Some(glob) => glob_to_regex(glob),
+            None => source.to_string(),
+        };
+
+        Ok(Self {
+            source: source.to_string(),
+            regex: Regex::new(&format!("^(?:{regex})$"))?,
+        })
+    }
+
+    pub(crate) fn is_match(&self, value: &str) -> bool {
+        self.regex.is_match(value)
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::new();
+
+    for c in glob.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+
+    regex
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Self::new(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::probe::filter::{
+        Filter,
+        Pattern,
+    };
+
+    #[test]
+    fn empty_matches_everything() {
+        assert!(Filter::default().is_match("ext4"));
+    }
+
+    #[test]
+    fn whole_value() {
+        let filter = Filter::exclude(["tmpfs"]);
+
+        assert!(!filter.is_match("tmpfs"));
+        assert!(filter.is_match("devtmpfs"));
+    }
+
+    #[test]
+    fn include_and_exclude() {
+        let filter = Filter {
+            include: vec![Pattern::new("/srv/.*").unwrap()],
+            exclude: vec![Pattern::new("/srv/tmp").unwrap()],
+        };
+
+        assert!(filter.is_match("/srv/data"));
+        assert!(!filter.is_match("/srv/tmp"));
+        assert!(!filter.is_match("/home"));
+    }
+
+    #[test]
+    fn glob() {
+        let filter = Filter::exclude(["glob:system_cpu_*", "glob:eth?.1"]);
+
+        assert!(!filter.is_match("system_cpu_seconds_total"));
+        assert!(!filter.is_match("eth0.1"));
+        assert!(filter.is_match("eth0x1"));
+        assert!(filter.is_match("system_memory_byte"));
+        assert_eq!("glob:eth?.1", filter.exclude[1].to_string());
+    }
+}
@@ -0,0 +1,188 @@
+use anyhow::Error;
+use axum::{
+    async_trait,
+    extract::{
+        FromRequestParts,
+        Query,
+    },
+    http::{
+        header,
+        request::Parts,
+        HeaderMap,
+    },
+};
+use chrono::Utc;
+use prometheus::{
+    proto::MetricFamily,
+    Encoder,
+    TextEncoder,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::probe::ProbeError;
+
+mod influx;
+mod json;
+mod openmetrics;
+pub(crate) mod otlp;
+
+/// Exposition formats the probes can respond with. Extracted from the
+/// `format` query parameter or otherwise the `Accept` header of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Format {
+    /// The prometheus text format in version 0.0.4.
+    Prometheus,
+
+    /// The `OpenMetrics` text format in version 1.0.0.
+    OpenMetrics,
+
+    /// A JSON array of the metric families, see the README for the schema.
+    Json,
+
+    /// The influxdb line protocol.
+    Influx,
+
+    /// An OTLP/JSON export request like OTLP/HTTP receivers accept it.
+    Otlp,
+}
+
+#[derive(Debug, Deserialize)]
+struct Params {
+    format: Option<Format>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Format
+where
+    S: Send + Sync,
+{
+    type Rejection = ProbeError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(params) = Query::<Params>::from_request_parts(parts, state)
+            .await
+            .map_err(|err| ProbeError::BadRequest(err.body_text()))?;
+
+        Ok(params
+            .format
+            .unwrap_or_else(|| Self::from_headers(&parts.headers)))
+    }
+}
+
+impl Format {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        headers
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .map_or(Self::Prometheus, Self::from_accept)
+    }
+
+    /// Picks the supported format with the highest quality from an `Accept`
+    /// header. Falls back to the prometheus text format if nothing else is
+    /// accepted as scrapers usually understand it anyway.
+    fn from_accept(accept: &str) -> Self {
+        let mut best: Option<(Self, f32)> = None;
+
+        for range in accept.split(',') {
+            let mut parts = range.split(';').map(str::trim);
+
+            let format = match parts.next().unwrap_or_default() {
+                "application/openmetrics-text" => Self::OpenMetrics,
+                "application/json" => Self::Json,
+                "text/plain" | "text/*" | "*/*" => Self::Prometheus,
+                _ => continue,
+            };
+
+            let quality = parts
+                .filter_map(|param| param.strip_prefix("q="))
+                .find_map(|quality| quality.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            if quality > 0.0 && best.map_or(true, |(_, best)| quality > best) {
+                best = Some((format, quality));
+            }
+        }
+
+        best.map_or(Self::Prometheus, |(format, _)| format)
+    }
+
+    pub(crate) fn content_type(self) -> &'static str {
+        match self {
+            Self::Prometheus => "text/plain; version=0.0.4; charset=utf-8",
+            Self::OpenMetrics => "application/openmetrics-text; version=1.0.0; charset=utf-8",
+            Self::Json | Self::Otlp => "application/json",
+            Self::Influx => "text/plain; charset=utf-8",
+        }
+    }
+
+    pub(crate) fn encode(self, families: &[MetricFamily]) -> Result<Vec<u8>, Error> {
+        let now = Utc::now();
+
+        match self {
+            Self::Prometheus => {
+                let mut buffer = vec![];
+                TextEncoder::new().encode(families, &mut buffer)?;
+
+                Ok(buffer)
+            }
+
+            Self::OpenMetrics => Ok(openmetrics::encode(families)?.into_bytes()),
+            Self::Json => Ok(json::encode(families, now.timestamp_millis())?),
+            Self::Influx => Ok(influx::encode(
+                families,
+                now.timestamp_nanos_opt().unwrap_or_default(),
+            )?
+            .into_bytes()),
+            Self::Otlp => Ok(otlp::encode(families, now.timestamp_millis())?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod accept {
+        use pretty_assertions::assert_eq;
+
+        use crate::probe::format::Format;
+
+        #[test]
+        fn prometheus_scrape() {
+            const INPUT: &str = "application/openmetrics-text;version=1.0.0,application/\
+                                 openmetrics-text;version=0.0.1;q=0.75,text/plain;version=0.0.4;\
+                                 q=0.5,*/*;q=0.1";
+
+            assert_eq!(Format::OpenMetrics, Format::from_accept(INPUT));
+        }
+
+        #[test]
+        fn quality() {
+            const INPUT: &str = "application/openmetrics-text;q=0.2, text/plain;q=0.8";
+
+            assert_eq!(Format::Prometheus, Format::from_accept(INPUT));
+        }
+
+        #[test]
+        fn json() {
+            const INPUT: &str = "application/json";
+
+            assert_eq!(Format::Json, Format::from_accept(INPUT));
+        }
+
+        #[test]
+        fn unsupported() {
+            assert_eq!(Format::Prometheus, Format::from_accept("application/xml"));
+            assert_eq!(Format::Prometheus, Format::from_accept(""));
+        }
+
+        #[test]
+        fn not_acceptable() {
+            const INPUT: &str = "text/plain;q=0, application/openmetrics-text;q=0.1";
+
+            assert_eq!(Format::OpenMetrics, Format::from_accept(INPUT));
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{
+        self,
+        Write,
+    },
+};
+
+use prometheus::proto::{
+    MetricFamily,
+    MetricType,
+};
+
+/// Prefixes that are part of the measurement together with the next part of
+/// the metric name, so `system_cpu_seconds_total` ends up in `system_cpu`
+/// instead of `system`.
+const NAMESPACES: &[&str] = &["system"];
+
+/// Samples with the same measurement, tags and timestamp are written as one
+/// line with multiple fields.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Line {
+    measurement: String,
+    tags: Vec<(String, String)>,
+    timestamp: i64,
+}
+
+/// Encodes `families` in the influxdb line protocol. The measurement is the
+/// prefix of the metric name and the rest of the name is the field. Metrics
+/// without their own timestamp get `timestamp` in nanoseconds since the unix
+/// epoch.
+pub(super) fn encode(families: &[MetricFamily], timestamp: i64) -> Result<String, fmt::Error> {
+    let mut lines: BTreeMap<Line, Vec<(String, f64)>> = BTreeMap::new();
+
+    for family in families {
+        let (measurement, field) = split(family.get_name());
+
+        for metric in family.get_metric() {
+            let timestamp = match metric.get_timestamp_ms() {
+                0 => timestamp,
+                millis => millis * 1_000_000,
+            };
+
+            let mut add = |suffix: &str, tag: Option<(&str, f64)>, value: f64| {
+                let mut tags = metric
+                    .get_label()
+                    .iter()
+                    .map(|label| (label.get_name().to_string(), label.get_value().to_string()))
+                    .collect::<Vec<_>>();
+
+                if let Some((name, value)) = tag {
+                    tags.push((name.to_string(), number(value)));
+                    tags.sort();
+                }
+
+                let line = Line {
+                    measurement: measurement.to_string(),
+                    tags,
+                    timestamp,
+                };
+
+                lines
+                    .entry(line)
+                    .or_default()
+                    .push((format!("{field}{suffix}"), value));
+            };
+
+            match family.get_field_type() {
+                MetricType::COUNTER => add("", None, metric.get_counter().get_value()),
+                MetricType::GAUGE => add("", None, metric.get_gauge().get_value()),
+                MetricType::UNTYPED => add("", None, metric.get_untyped().get_value()),
+
+                #[allow(clippy::cast_precision_loss)]
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+
+                    for bucket in histogram.get_bucket() {
+                        add(
+                            "_bucket",
+                            Some(("le", bucket.get_upper_bound())),
+                            bucket.get_cumulative_count() as f64,
+                        );
+                    }
+
+                    add("_count", None, histogram.get_sample_count() as f64);
+                    add("_sum", None, histogram.get_sample_sum());
+                }
+
+                #[allow(clippy::cast_precision_loss)]
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+
+                    for quantile in summary.get_quantile() {
+                        add(
+                            "",
+                            Some(("quantile", quantile.get_quantile())),
+                            quantile.get_value(),
+                        );
+                    }
+
+                    add("_count", None, summary.get_sample_count() as f64);
+                    add("_sum", None, summary.get_sample_sum());
+                }
+            }
+        }
+    }
+
+    let mut out = String::new();
+
+    for (line, fields) in lines {
+        // The line protocol has no representation for NaN and infinity.
+        let fields = fields
+            .iter()
+            .filter(|(_, value)| value.is_finite())
+            .map(|(name, value)| format!("{}={value}", escape(name, false)))
+            .collect::<Vec<_>>();
+
+        if fields.is_empty() {
+            continue;
+        }
+
+        out.push_str(&escape(&line.measurement, true));
+
+        // Empty tag values are not allowed, they are the same as no tag.
+        for (name, value) in line.tags.iter().filter(|(_, value)| !value.is_empty()) {
+            write!(out, ",{}={}", escape(name, false), escape(value, false))?;
+        }
+
+        writeln!(out, " {} {}", fields.join(","), line.timestamp)?;
+    }
+
+    Ok(out)
+}
+
+/// Splits a metric name into measurement and field.
+fn split(name: &str) -> (&str, &str) {
+    let Some((prefix, rest)) = name.split_once('_') else {
+        return (name, "value");
+    };
+
+    if !NAMESPACES.contains(&prefix) {
+        return (prefix, rest);
+    }
+
+    match rest.split_once('_') {
+        Some((probe, field)) => (&name[..prefix.len() + 1 + probe.len()], field),
+        None => (name, "value"),
+    }
+}
+
+fn number(value: f64) -> String {
+    if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Escapes commas and spaces and for keys and tag values also equal signs.
+/// Line breaks can not be escaped and would end the line so they are
+/// replaced with spaces.
+fn escape(value: &str, measurement: bool) -> String {
+    let mut out = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        let c = match c {
+            '\n' | '\r' => ' ',
+            c => c,
+        };
+
+        match c {
+            ',' | ' ' => out.push('\\'),
+            '=' if !measurement => out.push('\\'),
+            _ => {}
+        }
+
+        out.push(c);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    mod encode {
+        use pretty_assertions::assert_eq;
+        use prometheus::{
+            register_counter_vec_with_registry,
+            register_gauge_vec_with_registry,
+            register_gauge_with_registry,
+            register_histogram_with_registry,
+            Registry,
+        };
+
+        use crate::probe::format::influx::encode;
+
+        #[test]
+        fn fields_are_grouped() {
+            const EXPECTED: &str = "\
+info value=1 1700000000000000000
+system_cpu,cpu=total,mode=user seconds_total=12.5 1700000000000000000
+system_load 1=0.5,15=0.25 1700000000000000000
+";
+
+            let registry = Registry::new();
+
+            register_counter_vec_with_registry!(
+                "system_cpu_seconds_total",
+                "seconds the cpus spent in each mode",
+                &["cpu", "mode"],
+                registry
+            )
+            .unwrap()
+            .with_label_values(&["total", "user"])
+            .inc_by(12.5);
+
+            register_gauge_with_registry!("system_load_1", "load", registry)
+                .unwrap()
+                .set(0.5);
+
+            register_gauge_with_registry!("system_load_15", "load", registry)
+                .unwrap()
+                .set(0.25);
+
+            register_gauge_with_registry!("info", "info", registry)
+                .unwrap()
+                .set(1.0);
+
+            let got = encode(&registry.gather(), 1_700_000_000_000_000_000).unwrap();
+
+            assert_eq!(EXPECTED, got);
+        }
+
+        #[test]
+        fn escape_tags() {
+            const EXPECTED: &str =
+                "system_filesystem,mountpoint=/mnt/a\\ b\\,c\\=d\\ e size_byte=1 0\n";
+
+            let registry = Registry::new();
+
+            register_gauge_vec_with_registry!(
+                "system_filesystem_size_byte",
+                "size",
+                &["mountpoint", "device"],
+                registry
+            )
+            .unwrap()
+            .with_label_values(&["/mnt/a b,c=d\ne", ""])
+            .set(1.0);
+
+            let got = encode(&registry.gather(), 0).unwrap();
+
+            assert_eq!(EXPECTED, got);
+        }
+
+        #[test]
+        fn histogram() {
+            const EXPECTED: &str = "\
+request seconds_count=2,seconds_sum=2.5 0
+request,le=1 seconds_bucket=1 0
+";
+
+            let registry = Registry::new();
+
+            let histogram = register_histogram_with_registry!(
+                "request_seconds",
+                "how long requests took",
+                vec![1.0],
+                registry
+            )
+            .unwrap();
+
+            histogram.observe(0.5);
+            histogram.observe(2.0);
+
+            let got = encode(&registry.gather(), 0).unwrap();
+
+            assert_eq!(EXPECTED, got);
+        }
+    }
+}
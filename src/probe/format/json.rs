@@ -0,0 +1,277 @@
+use std::collections::BTreeMap;
+
+use prometheus::proto::{
+    Metric,
+    MetricFamily,
+    MetricType,
+};
+use serde::{
+    Serialize,
+    Serializer,
+};
+
+/// One metric family with all of its metrics.
+#[derive(Debug, Serialize)]
+struct Family<'a> {
+    name: &'a str,
+    help: &'a str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    metrics: Vec<Sample<'a>>,
+}
+
+/// One metric of a family. Counters, gauges and untyped metrics have a
+/// `value`, histograms have `buckets`, summaries have `quantiles` and both
+/// have `count` and `sum`. Bounds and quantiles are keyed like the `le` and
+/// `quantile` labels of the prometheus text format.
+#[derive(Debug, Serialize)]
+struct Sample<'a> {
+    labels: BTreeMap<&'a str, &'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<Number>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    buckets: Option<BTreeMap<String, u64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quantiles: Option<BTreeMap<String, Number>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sum: Option<Number>,
+    timestamp: i64,
+}
+
+/// A sample value. JSON has no numbers for `NaN` and the infinities so they
+/// are written as the strings `"NaN"`, `"+Inf"` and `"-Inf"` like in the
+/// prometheus text format.
+#[derive(Debug)]
+struct Number(f64);
+
+/// Encodes `families` as a JSON array. Metrics without their own timestamp
+/// get `timestamp` in milliseconds since the unix epoch.
+pub(super) fn encode(
+    families: &[MetricFamily],
+    timestamp: i64,
+) -> Result<Vec<u8>, serde_json::Error> {
+    let families = families
+        .iter()
+        .map(|family| Family {
+            name: family.get_name(),
+            help: family.get_help(),
+            kind: match family.get_field_type() {
+                MetricType::COUNTER => "counter",
+                MetricType::GAUGE => "gauge",
+                MetricType::HISTOGRAM => "histogram",
+                MetricType::SUMMARY => "summary",
+                MetricType::UNTYPED => "untyped",
+            },
+            metrics: family
+                .get_metric()
+                .iter()
+                .map(|metric| Sample::new(family.get_field_type(), metric, timestamp))
+                .collect(),
+        })
+        .collect::<Vec<_>>();
+
+    serde_json::to_vec(&families)
+}
+
+impl<'a> Sample<'a> {
+    fn new(metric_type: MetricType, metric: &'a Metric, timestamp: i64) -> Self {
+        let mut sample = Self {
+            labels: metric
+                .get_label()
+                .iter()
+                .map(|label| (label.get_name(), label.get_value()))
+                .collect(),
+            value: None,
+            buckets: None,
+            quantiles: None,
+            count: None,
+            sum: None,
+            timestamp: match metric.get_timestamp_ms() {
+                0 => timestamp,
+                own => own,
+            },
+        };
+
+        match metric_type {
+            MetricType::COUNTER => sample.value = Some(Number(metric.get_counter().get_value())),
+            MetricType::GAUGE => sample.value = Some(Number(metric.get_gauge().get_value())),
+            MetricType::UNTYPED => sample.value = Some(Number(metric.get_untyped().get_value())),
+
+            MetricType::HISTOGRAM => {
+                let histogram = metric.get_histogram();
+
+                sample.buckets = Some(
+                    histogram
+                        .get_bucket()
+                        .iter()
+                        .map(|bucket| {
+                            (
+                                bound(bucket.get_upper_bound()),
+                                bucket.get_cumulative_count(),
+                            )
+                        })
+                        .collect(),
+                );
+                sample.count = Some(histogram.get_sample_count());
+                sample.sum = Some(Number(histogram.get_sample_sum()));
+            }
+
+            MetricType::SUMMARY => {
+                let summary = metric.get_summary();
+
+                sample.quantiles = Some(
+                    summary
+                        .get_quantile()
+                        .iter()
+                        .map(|quantile| {
+                            (bound(quantile.get_quantile()), Number(quantile.get_value()))
+                        })
+                        .collect(),
+                );
+                sample.count = Some(summary.get_sample_count());
+                sample.sum = Some(Number(summary.get_sample_sum()));
+            }
+        }
+
+        sample
+    }
+}
+
+impl Serialize for Number {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self.0 {
+            value if value.is_nan() => serializer.serialize_str("NaN"),
+            f64::INFINITY => serializer.serialize_str("+Inf"),
+            f64::NEG_INFINITY => serializer.serialize_str("-Inf"),
+            value => serializer.serialize_f64(value),
+        }
+    }
+}
+
+fn bound(value: f64) -> String {
+    if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod encode {
+        use pretty_assertions::assert_eq;
+        use prometheus::{
+            register_counter_vec_with_registry,
+            register_gauge_vec_with_registry,
+            register_histogram_with_registry,
+            Registry,
+        };
+        use serde_json::json;
+
+        use crate::probe::format::json::encode;
+
+        #[test]
+        fn counter() {
+            let registry = Registry::new();
+
+            register_counter_vec_with_registry!(
+                "system_cpu_seconds_total",
+                "seconds the cpus spent in each mode",
+                &["cpu", "mode"],
+                registry
+            )
+            .unwrap()
+            .with_label_values(&["total", "user"])
+            .inc_by(12.5);
+
+            let expected = json!([{
+                "name": "system_cpu_seconds_total",
+                "help": "seconds the cpus spent in each mode",
+                "type": "counter",
+                "metrics": [{
+                    "labels": { "cpu": "total", "mode": "user" },
+                    "value": 12.5,
+                    "timestamp": 1_700_000_000_000_i64,
+                }],
+            }]);
+
+            let got: serde_json::Value =
+                serde_json::from_slice(&encode(&registry.gather(), 1_700_000_000_000).unwrap())
+                    .unwrap();
+
+            assert_eq!(expected, got);
+        }
+
+        #[test]
+        fn histogram() {
+            let registry = Registry::new();
+
+            let histogram = register_histogram_with_registry!(
+                "request_seconds",
+                "how long requests took",
+                vec![1.0],
+                registry
+            )
+            .unwrap();
+
+            histogram.observe(0.5);
+            histogram.observe(2.0);
+
+            let expected = json!([{
+                "name": "request_seconds",
+                "help": "how long requests took",
+                "type": "histogram",
+                "metrics": [{
+                    "labels": {},
+                    "buckets": { "1": 1 },
+                    "count": 2,
+                    "sum": 2.5,
+                    "timestamp": 0,
+                }],
+            }]);
+
+            let got: serde_json::Value =
+                serde_json::from_slice(&encode(&registry.gather(), 0).unwrap()).unwrap();
+
+            assert_eq!(expected, got);
+        }
+
+        #[test]
+        fn non_finite() {
+            let registry = Registry::new();
+
+            let gauge = register_gauge_vec_with_registry!(
+                "ping_rtt_seconds",
+                "round trip time",
+                &["target"],
+                registry
+            )
+            .unwrap();
+
+            gauge.with_label_values(&["a"]).set(f64::NAN);
+            gauge.with_label_values(&["b"]).set(f64::INFINITY);
+            gauge.with_label_values(&["c"]).set(f64::NEG_INFINITY);
+
+            let expected = json!([{
+                "name": "ping_rtt_seconds",
+                "help": "round trip time",
+                "type": "gauge",
+                "metrics": [
+                    { "labels": { "target": "a" }, "value": "NaN", "timestamp": 0 },
+                    { "labels": { "target": "b" }, "value": "+Inf", "timestamp": 0 },
+                    { "labels": { "target": "c" }, "value": "-Inf", "timestamp": 0 },
+                ],
+            }]);
+
+            let got: serde_json::Value =
+                serde_json::from_slice(&encode(&registry.gather(), 0).unwrap()).unwrap();
+
+            assert_eq!(expected, got);
+        }
+    }
+}
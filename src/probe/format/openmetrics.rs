@@ -0,0 +1,330 @@
+use std::fmt::{
+    self,
+    Write,
+};
+
+use prometheus::proto::{
+    LabelPair,
+    Metric,
+    MetricFamily,
+    MetricType,
+};
+
+/// Name suffixes that are exposed as the unit of a metric family.
+const UNITS: &[&str] = &[
+    "seconds",
+    "milliseconds",
+    "bytes",
+    "byte",
+    "percent",
+    "ratio",
+];
+
+/// Encodes `families` in the `OpenMetrics` text format. Counters are exposed
+/// without the `_total` suffix in the family name and with it on the sample.
+pub(super) fn encode(families: &[MetricFamily]) -> Result<String, fmt::Error> {
+    let mut out = String::new();
+
+    for family in families {
+        let metric_type = family.get_field_type();
+        let name = family.get_name();
+        let name = match metric_type {
+            MetricType::COUNTER => name.strip_suffix("_total").unwrap_or(name),
+            _ => name,
+        };
+
+        let kind = match metric_type {
+            MetricType::COUNTER => "counter",
+            MetricType::GAUGE => "gauge",
+            MetricType::HISTOGRAM => "histogram",
+            MetricType::SUMMARY => "summary",
+            MetricType::UNTYPED => "unknown",
+        };
+
+        writeln!(out, "# TYPE {name} {kind}")?;
+
+        if let Some(unit) = UNITS.iter().find(|unit| {
+            name.strip_suffix(*unit)
+                .is_some_and(|name| name.ends_with('_'))
+        }) {
+            writeln!(out, "# UNIT {name} {unit}")?;
+        }
+
+        if !family.get_help().is_empty() {
+            writeln!(out, "# HELP {name} {}", escape(family.get_help()))?;
+        }
+
+        for metric in family.get_metric() {
+            let labels = metric.get_label();
+
+            match metric_type {
+                MetricType::COUNTER => sample(
+                    &mut out,
+                    &format!("{name}_total"),
+                    labels,
+                    None,
+                    metric.get_counter().get_value(),
+                )?,
+
+                MetricType::GAUGE => {
+                    sample(&mut out, name, labels, None, metric.get_gauge().get_value())?;
+                }
+
+                MetricType::UNTYPED => {
+                    sample(
+                        &mut out,
+                        name,
+                        labels,
+                        None,
+                        metric.get_untyped().get_value(),
+                    )?;
+                }
+
+                MetricType::HISTOGRAM => histogram(&mut out, name, metric)?,
+                MetricType::SUMMARY => summary(&mut out, name, metric)?,
+            }
+        }
+    }
+
+    out.push_str("# EOF\n");
+
+    Ok(out)
+}
+
+fn histogram(out: &mut String, name: &str, metric: &Metric) -> fmt::Result {
+    let histogram = metric.get_histogram();
+    let labels = metric.get_label();
+    let bucket_name = format!("{name}_bucket");
+    let mut has_inf = false;
+
+    for bucket in histogram.get_bucket() {
+        has_inf |= bucket.get_upper_bound().is_infinite();
+
+        #[allow(clippy::cast_precision_loss)]
+        sample(
+            out,
+            &bucket_name,
+            labels,
+            Some(("le", bucket.get_upper_bound())),
+            bucket.get_cumulative_count() as f64,
+        )?;
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    let count = histogram.get_sample_count() as f64;
+
+    if !has_inf {
+        sample(
+            out,
+            &bucket_name,
+            labels,
+            Some(("le", f64::INFINITY)),
+            count,
+        )?;
+    }
+
+    sample(out, &format!("{name}_count"), labels, None, count)?;
+    sample(
+        out,
+        &format!("{name}_sum"),
+        labels,
+        None,
+        histogram.get_sample_sum(),
+    )
+}
+
+fn summary(out: &mut String, name: &str, metric: &Metric) -> fmt::Result {
+    let summary = metric.get_summary();
+    let labels = metric.get_label();
+
+    for quantile in summary.get_quantile() {
+        sample(
+            out,
+            name,
+            labels,
+            Some(("quantile", quantile.get_quantile())),
+            quantile.get_value(),
+        )?;
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    sample(
+        out,
+        &format!("{name}_count"),
+        labels,
+        None,
+        summary.get_sample_count() as f64,
+    )?;
+    sample(
+        out,
+        &format!("{name}_sum"),
+        labels,
+        None,
+        summary.get_sample_sum(),
+    )
+}
+
+fn sample(
+    out: &mut String,
+    name: &str,
+    labels: &[LabelPair],
+    additional: Option<(&str, f64)>,
+    value: f64,
+) -> fmt::Result {
+    out.push_str(name);
+
+    let mut separator = '{';
+
+    for label in labels {
+        let (name, value) = (label.get_name(), escape(label.get_value()));
+        write!(out, "{separator}{name}=\"{value}\"")?;
+        separator = ',';
+    }
+
+    if let Some((name, value)) = additional {
+        write!(out, "{separator}{name}=\"{}\"", number(value))?;
+        separator = ',';
+    }
+
+    if separator == ',' {
+        out.push('}');
+    }
+
+    writeln!(out, " {}", number(value))
+}
+
+fn number(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Escapes backslashes, new lines and double quotes.
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str(r"\\"),
+            '\n' => out.push_str(r"\n"),
+            '"' => out.push_str("\\\""),
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    mod encode {
+        use pretty_assertions::assert_eq;
+        use prometheus::{
+            register_counter_vec_with_registry,
+            register_gauge_vec_with_registry,
+            register_gauge_with_registry,
+            register_histogram_with_registry,
+            Registry,
+        };
+
+        use crate::probe::format::openmetrics::encode;
+
+        #[test]
+        fn counter_and_gauge() {
+            const EXPECTED: &str = r#"# TYPE system_cpu_seconds counter
+# UNIT system_cpu_seconds seconds
+# HELP system_cpu_seconds time the cpus spent in each mode
+system_cpu_seconds_total{cpu="total",mode="user"} 12.5
+# TYPE system_load_1 gauge
+# HELP system_load_1 system load average over 1 minute
+system_load_1 0.5
+# EOF
+"#;
+
+            let registry = Registry::new();
+
+            register_counter_vec_with_registry!(
+                "system_cpu_seconds_total",
+                "time the cpus spent in each mode",
+                &["cpu", "mode"],
+                registry
+            )
+            .unwrap()
+            .with_label_values(&["total", "user"])
+            .inc_by(12.5);
+
+            register_gauge_with_registry!(
+                "system_load_1",
+                "system load average over 1 minute",
+                registry
+            )
+            .unwrap()
+            .set(0.5);
+
+            let got = encode(&registry.gather()).unwrap();
+
+            assert_eq!(EXPECTED, got);
+        }
+
+        #[test]
+        fn histogram() {
+            const EXPECTED: &str = r#"# TYPE request_seconds histogram
+# UNIT request_seconds seconds
+# HELP request_seconds how long \"requests\" took
+request_seconds_bucket{le="1"} 1
+request_seconds_bucket{le="+Inf"} 2
+request_seconds_count 2
+request_seconds_sum 2.5
+# EOF
+"#;
+
+            let registry = Registry::new();
+
+            let histogram = register_histogram_with_registry!(
+                "request_seconds",
+                "how long \"requests\" took",
+                vec![1.0],
+                registry
+            )
+            .unwrap();
+
+            histogram.observe(0.5);
+            histogram.observe(2.0);
+
+            let got = encode(&registry.gather()).unwrap();
+
+            assert_eq!(EXPECTED, got);
+        }
+
+        #[test]
+        fn escape_label_value() {
+            const EXPECTED: &str = r#"# TYPE mounted gauge
+# HELP mounted if a path is mounted
+mounted{path="C:\\data \"x\"\n"} 1
+# EOF
+"#;
+
+            let registry = Registry::new();
+
+            register_gauge_vec_with_registry!(
+                "mounted",
+                "if a path is mounted",
+                &["path"],
+                registry
+            )
+            .unwrap()
+            .with_label_values(&["C:\\data \"x\"\n"])
+            .set(1.0);
+
+            let got = encode(&registry.gather()).unwrap();
+
+            assert_eq!(EXPECTED, got);
+        }
+    }
+}
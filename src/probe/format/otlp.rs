@@ -0,0 +1,565 @@
+use std::{
+    collections::BTreeMap,
+    sync::LazyLock,
+};
+
+use chrono::Utc;
+use prometheus::proto::{
+    self,
+    MetricFamily,
+    MetricType,
+};
+use prost::{
+    Message,
+    Oneof,
+};
+use serde::{
+    Serialize,
+    Serializer,
+};
+
+use crate::probe::info::VERSION;
+
+/// `AGGREGATION_TEMPORALITY_CUMULATIVE` of the `AggregationTemporality` enum.
+const CUMULATIVE: i32 = 2;
+
+/// When callipe-rs started in milliseconds since the unix epoch. Used as the
+/// start time of cumulative sums, histograms and summaries.
+pub(crate) static STARTED: LazyLock<i64> = LazyLock::new(|| Utc::now().timestamp_millis());
+
+// Messages of the OTLP metrics protocol as defined in
+// opentelemetry/proto/collector/metrics/v1/metrics_service.proto and the files
+// it imports, limited to what callipe-rs sends. The JSON representation
+// follows the OTLP/JSON encoding with camel case names and 64 bit integers as
+// strings.
+
+#[derive(Clone, PartialEq, Message, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ExportMetricsServiceRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub(crate) resource_metrics: Vec<ResourceMetrics>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct ExportMetricsServiceResponse {}
+
+#[derive(Clone, PartialEq, Message, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ResourceMetrics {
+    #[prost(message, optional, tag = "1")]
+    pub(crate) resource: Option<Resource>,
+    #[prost(message, repeated, tag = "2")]
+    pub(crate) scope_metrics: Vec<ScopeMetrics>,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize)]
+pub(crate) struct Resource {
+    #[prost(message, repeated, tag = "1")]
+    pub(crate) attributes: Vec<KeyValue>,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize)]
+pub(crate) struct KeyValue {
+    #[prost(string, tag = "1")]
+    pub(crate) key: String,
+    #[prost(message, optional, tag = "2")]
+    pub(crate) value: Option<AnyValue>,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize)]
+pub(crate) struct AnyValue {
+    #[prost(oneof = "Value", tags = "1")]
+    #[serde(flatten)]
+    pub(crate) value: Option<Value>,
+}
+
+#[derive(Clone, PartialEq, Oneof, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum Value {
+    #[prost(string, tag = "1")]
+    StringValue(String),
+}
+
+#[derive(Clone, PartialEq, Message, Serialize)]
+pub(crate) struct ScopeMetrics {
+    #[prost(message, optional, tag = "1")]
+    pub(crate) scope: Option<InstrumentationScope>,
+    #[prost(message, repeated, tag = "2")]
+    pub(crate) metrics: Vec<Metric>,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize)]
+pub(crate) struct InstrumentationScope {
+    #[prost(string, tag = "1")]
+    pub(crate) name: String,
+    #[prost(string, tag = "2")]
+    pub(crate) version: String,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize)]
+pub(crate) struct Metric {
+    #[prost(string, tag = "1")]
+    pub(crate) name: String,
+    #[prost(string, tag = "2")]
+    pub(crate) description: String,
+    #[prost(oneof = "Data", tags = "5, 7, 9, 11")]
+    #[serde(flatten)]
+    pub(crate) data: Option<Data>,
+}
+
+#[derive(Clone, PartialEq, Oneof, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum Data {
+    #[prost(message, tag = "5")]
+    Gauge(Gauge),
+    #[prost(message, tag = "7")]
+    Sum(Sum),
+    #[prost(message, tag = "9")]
+    Histogram(Histogram),
+    #[prost(message, tag = "11")]
+    Summary(Summary),
+}
+
+#[derive(Clone, PartialEq, Message, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Gauge {
+    #[prost(message, repeated, tag = "1")]
+    pub(crate) data_points: Vec<NumberDataPoint>,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Sum {
+    #[prost(message, repeated, tag = "1")]
+    pub(crate) data_points: Vec<NumberDataPoint>,
+    #[prost(int32, tag = "2")]
+    pub(crate) aggregation_temporality: i32,
+    #[prost(bool, tag = "3")]
+    pub(crate) is_monotonic: bool,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Histogram {
+    #[prost(message, repeated, tag = "1")]
+    pub(crate) data_points: Vec<HistogramDataPoint>,
+    #[prost(int32, tag = "2")]
+    pub(crate) aggregation_temporality: i32,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Summary {
+    #[prost(message, repeated, tag = "1")]
+    pub(crate) data_points: Vec<SummaryDataPoint>,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct NumberDataPoint {
+    #[prost(message, repeated, tag = "7")]
+    pub(crate) attributes: Vec<KeyValue>,
+    /// Only set for sums, gauges have no start.
+    #[prost(fixed64, tag = "2")]
+    #[serde(serialize_with = "string", skip_serializing_if = "is_zero")]
+    pub(crate) start_time_unix_nano: u64,
+    #[prost(fixed64, tag = "3")]
+    #[serde(serialize_with = "string")]
+    pub(crate) time_unix_nano: u64,
+    #[prost(oneof = "NumberValue", tags = "4")]
+    #[serde(flatten)]
+    pub(crate) value: Option<NumberValue>,
+}
+
+#[derive(Clone, PartialEq, Oneof, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum NumberValue {
+    #[prost(double, tag = "4")]
+    AsDouble(f64),
+}
+
+#[derive(Clone, PartialEq, Message, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct HistogramDataPoint {
+    #[prost(message, repeated, tag = "9")]
+    pub(crate) attributes: Vec<KeyValue>,
+    #[prost(fixed64, tag = "2")]
+    #[serde(serialize_with = "string")]
+    pub(crate) start_time_unix_nano: u64,
+    #[prost(fixed64, tag = "3")]
+    #[serde(serialize_with = "string")]
+    pub(crate) time_unix_nano: u64,
+    #[prost(fixed64, tag = "4")]
+    #[serde(serialize_with = "string")]
+    pub(crate) count: u64,
+    #[prost(double, optional, tag = "5")]
+    pub(crate) sum: Option<f64>,
+    #[prost(fixed64, repeated, tag = "6")]
+    #[serde(serialize_with = "strings")]
+    pub(crate) bucket_counts: Vec<u64>,
+    #[prost(double, repeated, tag = "7")]
+    pub(crate) explicit_bounds: Vec<f64>,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SummaryDataPoint {
+    #[prost(message, repeated, tag = "7")]
+    pub(crate) attributes: Vec<KeyValue>,
+    #[prost(fixed64, tag = "2")]
+    #[serde(serialize_with = "string")]
+    pub(crate) start_time_unix_nano: u64,
+    #[prost(fixed64, tag = "3")]
+    #[serde(serialize_with = "string")]
+    pub(crate) time_unix_nano: u64,
+    #[prost(fixed64, tag = "4")]
+    #[serde(serialize_with = "string")]
+    pub(crate) count: u64,
+    #[prost(double, tag = "5")]
+    pub(crate) sum: f64,
+    #[prost(message, repeated, tag = "6")]
+    pub(crate) quantile_values: Vec<ValueAtQuantile>,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize)]
+pub(crate) struct ValueAtQuantile {
+    #[prost(double, tag = "1")]
+    pub(crate) quantile: f64,
+    #[prost(double, tag = "2")]
+    pub(crate) value: f64,
+}
+
+// serde passes fields by reference.
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn string<S: Serializer>(value: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+// serde passes fields by reference.
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+fn strings<S: Serializer>(values: &[u64], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(values.iter().map(ToString::to_string))
+}
+
+/// Encodes `families` as an OTLP/JSON export request with the default
+/// resource attributes. Metrics without their own timestamp get `timestamp`
+/// in milliseconds since the unix epoch.
+pub(super) fn encode(
+    families: &[MetricFamily],
+    timestamp: i64,
+) -> Result<Vec<u8>, serde_json::Error> {
+    serde_json::to_vec(&request(
+        families,
+        resource(&BTreeMap::new()),
+        timestamp,
+        *STARTED,
+    ))
+}
+
+/// Attributes of the host callipe-rs runs on together with `extra`.
+pub(crate) fn resource(extra: &BTreeMap<String, String>) -> Resource {
+    let mut attributes = BTreeMap::from([
+        ("service.name".to_string(), "callipe-rs".to_string()),
+        ("service.version".to_string(), VERSION.to_string()),
+    ]);
+
+    if let Ok(host) = hostname::get() {
+        attributes.insert("host.name".to_string(), host.to_string_lossy().into_owned());
+    }
+
+    attributes.extend(extra.clone());
+
+    Resource {
+        attributes: attributes
+            .into_iter()
+            .map(|(key, value)| key_value(key, value))
+            .collect(),
+    }
+}
+
+/// Maps gauges and untyped metrics to gauges, counters to cumulative
+/// monotonic sums, histograms to cumulative histograms and summaries to
+/// summaries. Metrics without their own timestamp get `timestamp` and the
+/// cumulative ones start at `start`, both in milliseconds since the unix
+/// epoch.
+pub(crate) fn request(
+    families: &[MetricFamily],
+    resource: Resource,
+    timestamp: i64,
+    start: i64,
+) -> ExportMetricsServiceRequest {
+    let start_time_unix_nano = unix_nano(start);
+
+    let metrics = families
+        .iter()
+        .map(|family| {
+            let points = family.get_metric().iter().map(|metric| {
+                let millis = match metric.get_timestamp_ms() {
+                    0 => timestamp,
+                    own => own,
+                };
+
+                let attributes = metric
+                    .get_label()
+                    .iter()
+                    .map(|label| key_value(label.get_name(), label.get_value()))
+                    .collect::<Vec<_>>();
+
+                (metric, attributes, unix_nano(millis))
+            });
+
+            let number =
+                |(metric, attributes, time_unix_nano): (&proto::Metric, _, _)| NumberDataPoint {
+                    attributes,
+                    start_time_unix_nano: match family.get_field_type() {
+                        MetricType::COUNTER => start_time_unix_nano,
+                        _ => 0,
+                    },
+                    time_unix_nano,
+                    value: Some(NumberValue::AsDouble(match family.get_field_type() {
+                        MetricType::COUNTER => metric.get_counter().get_value(),
+                        MetricType::UNTYPED => metric.get_untyped().get_value(),
+                        _ => metric.get_gauge().get_value(),
+                    })),
+                };
+
+            let data = match family.get_field_type() {
+                MetricType::GAUGE | MetricType::UNTYPED => Data::Gauge(Gauge {
+                    data_points: points.map(number).collect(),
+                }),
+
+                MetricType::COUNTER => Data::Sum(Sum {
+                    data_points: points.map(number).collect(),
+                    aggregation_temporality: CUMULATIVE,
+                    is_monotonic: true,
+                }),
+
+                MetricType::HISTOGRAM => Data::Histogram(Histogram {
+                    data_points: points
+                        .map(|(metric, attributes, time_unix_nano)| {
+                            histogram(
+                                metric.get_histogram(),
+                                attributes,
+                                start_time_unix_nano,
+                                time_unix_nano,
+                            )
+                        })
+                        .collect(),
+                    aggregation_temporality: CUMULATIVE,
+                }),
+
+                MetricType::SUMMARY => Data::Summary(Summary {
+                    data_points: points
+                        .map(|(metric, attributes, time_unix_nano)| {
+                            let summary = metric.get_summary();
+
+                            SummaryDataPoint {
+                                attributes,
+                                start_time_unix_nano,
+                                time_unix_nano,
+                                count: summary.get_sample_count(),
+                                sum: summary.get_sample_sum(),
+                                quantile_values: summary
+                                    .get_quantile()
+                                    .iter()
+                                    .map(|quantile| ValueAtQuantile {
+                                        quantile: quantile.get_quantile(),
+                                        value: quantile.get_value(),
+                                    })
+                                    .collect(),
+                            }
+                        })
+                        .collect(),
+                }),
+            };
+
+            Metric {
+                name: family.get_name().to_string(),
+                description: family.get_help().to_string(),
+                data: Some(data),
+            }
+        })
+        .collect();
+
+    ExportMetricsServiceRequest {
+        resource_metrics: vec![ResourceMetrics {
+            resource: Some(resource),
+            scope_metrics: vec![ScopeMetrics {
+                scope: Some(InstrumentationScope {
+                    name: "callipe-rs".to_string(),
+                    version: VERSION.to_string(),
+                }),
+                metrics,
+            }],
+        }],
+    }
+}
+
+/// Prometheus buckets are cumulative and OTLP buckets are not. OTLP also has
+/// an implicit bucket for everything above the last bound.
+fn histogram(
+    histogram: &proto::Histogram,
+    attributes: Vec<KeyValue>,
+    start_time_unix_nano: u64,
+    time_unix_nano: u64,
+) -> HistogramDataPoint {
+    let mut explicit_bounds = Vec::new();
+    let mut bucket_counts = Vec::new();
+    let mut previous = 0;
+
+    for bucket in histogram.get_bucket() {
+        if !bucket.get_upper_bound().is_finite() {
+            break;
+        }
+
+        explicit_bounds.push(bucket.get_upper_bound());
+        bucket_counts.push(bucket.get_cumulative_count().saturating_sub(previous));
+        previous = bucket.get_cumulative_count();
+    }
+
+    bucket_counts.push(histogram.get_sample_count().saturating_sub(previous));
+
+    HistogramDataPoint {
+        attributes,
+        start_time_unix_nano,
+        time_unix_nano,
+        count: histogram.get_sample_count(),
+        sum: Some(histogram.get_sample_sum()),
+        bucket_counts,
+        explicit_bounds,
+    }
+}
+
+fn unix_nano(millis: i64) -> u64 {
+    u64::try_from(millis).unwrap_or_default() * 1_000_000
+}
+
+fn key_value(key: impl Into<String>, value: impl Into<String>) -> KeyValue {
+    KeyValue {
+        key: key.into(),
+        value: Some(AnyValue {
+            value: Some(Value::StringValue(value.into())),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod encode {
+        use pretty_assertions::assert_eq;
+        use prometheus::{
+            register_counter_vec_with_registry,
+            register_gauge_with_registry,
+            register_histogram_with_registry,
+            Registry,
+        };
+        use serde_json::json;
+
+        use crate::probe::format::otlp::{
+            request,
+            Resource,
+        };
+
+        #[test]
+        fn gauge_and_counter() {
+            let registry = Registry::new();
+
+            register_counter_vec_with_registry!(
+                "system_cpu_seconds_total",
+                "seconds the cpus spent in each mode",
+                &["cpu", "mode"],
+                registry
+            )
+            .unwrap()
+            .with_label_values(&["total", "user"])
+            .inc_by(12.5);
+
+            register_gauge_with_registry!("system_load_1", "load", registry)
+                .unwrap()
+                .set(0.5);
+
+            let expected = json!([
+                {
+                    "name": "system_cpu_seconds_total",
+                    "description": "seconds the cpus spent in each mode",
+                    "sum": {
+                        "dataPoints": [{
+                            "attributes": [
+                                { "key": "cpu", "value": { "stringValue": "total" } },
+                                { "key": "mode", "value": { "stringValue": "user" } },
+                            ],
+                            "startTimeUnixNano": "1690000000000000000",
+                            "timeUnixNano": "1700000000000000000",
+                            "asDouble": 12.5,
+                        }],
+                        "aggregationTemporality": 2,
+                        "isMonotonic": true,
+                    },
+                },
+                {
+                    "name": "system_load_1",
+                    "description": "load",
+                    "gauge": {
+                        "dataPoints": [{
+                            "attributes": [],
+                            "timeUnixNano": "1700000000000000000",
+                            "asDouble": 0.5,
+                        }],
+                    },
+                },
+            ]);
+
+            let got = request(
+                &registry.gather(),
+                Resource::default(),
+                1_700_000_000_000,
+                1_690_000_000_000,
+            );
+            let got =
+                serde_json::to_value(&got.resource_metrics[0].scope_metrics[0].metrics).unwrap();
+
+            assert_eq!(expected, got);
+        }
+
+        #[test]
+        fn histogram() {
+            let registry = Registry::new();
+
+            let histogram = register_histogram_with_registry!(
+                "request_seconds",
+                "how long requests took",
+                vec![1.0, 2.0],
+                registry
+            )
+            .unwrap();
+
+            histogram.observe(0.5);
+            histogram.observe(0.75);
+            histogram.observe(1.5);
+            histogram.observe(5.0);
+
+            let got = request(&registry.gather(), Resource::default(), 0, 0);
+            let got =
+                serde_json::to_value(&got.resource_metrics[0].scope_metrics[0].metrics).unwrap();
+
+            let expected = json!({
+                "dataPoints": [{
+                    "attributes": [],
+                    "startTimeUnixNano": "0",
+                    "timeUnixNano": "0",
+                    "count": "4",
+                    "sum": 7.75,
+                    "bucketCounts": ["2", "1", "1"],
+                    "explicitBounds": [1.0, 2.0],
+                }],
+                "aggregationTemporality": 2,
+            });
+
+            assert_eq!(expected, got[0]["histogram"]);
+        }
+    }
+}
@@ -1,40 +1,79 @@
+use anyhow::Error;
 use prometheus::{
     register_int_gauge_vec_with_registry,
-    Encoder,
     Registry,
-    TextEncoder,
 };
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    output::buffer,
+    probe::Probe,
+};
+
+/// Version of callipe-rs that is reported by the probe.
+pub(crate) const VERSION: &str = env!("VERGEN_BUILD_SEMVER");
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct Options {}
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct Params {}
+
+#[derive(Debug)]
+pub(crate) struct Info {}
+
+impl Probe for Info {
+    const NAME: &'static str = "info";
+    const LABELS: &'static [&'static str] = &[
+        "build_semver",
+        "build_timestamp",
+        "git_semver",
+        "git_branch",
+        "git_sha",
+        "output",
+        "path",
+    ];
+
+    type Options = Options;
+    type Params = Params;
+
+    async fn collect(
+        registry: &Registry,
+        _options: &Options,
+        _params: Params,
+    ) -> Result<(), Error> {
+        Self::run(registry)
+    }
+}
+
+impl Info {
+    fn run(registry: &Registry) -> Result<(), Error> {
+        register_int_gauge_vec_with_registry!(
+            "info",
+            "information abouot callipe-rs",
+            &[
+                "build_semver",
+                "build_timestamp",
+                "git_semver",
+                "git_branch",
+                "git_sha"
+            ],
+            registry
+        )?
+        .with_label_values(&[
+            VERSION,
+            env!("VERGEN_BUILD_TIMESTAMP"),
+            env!("VERGEN_GIT_SEMVER"),
+            env!("VERGEN_GIT_BRANCH"),
+            env!("VERGEN_GIT_SHA"),
+        ])
+        .set(1);
+
+        buffer::register(registry)?;
 
-#[allow(clippy::unused_async)]
-pub(crate) async fn handler() -> Vec<u8> {
-    let registry = Registry::new();
-
-    register_int_gauge_vec_with_registry!(
-        "info",
-        "information abouot callipe-rs",
-        &[
-            "build_semver",
-            "build_timestamp",
-            "git_semver",
-            "git_branch",
-            "git_sha"
-        ],
-        registry
-    )
-    .unwrap()
-    .with_label_values(&[
-        env!("VERGEN_BUILD_SEMVER"),
-        env!("VERGEN_BUILD_TIMESTAMP"),
-        env!("VERGEN_GIT_SEMVER"),
-        env!("VERGEN_GIT_BRANCH"),
-        env!("VERGEN_GIT_SHA"),
-    ])
-    .set(1);
-
-    let mut buffer = vec![];
-    let encoder = TextEncoder::new();
-    let metric_families = registry.gather();
-    encoder.encode(&metric_families, &mut buffer).unwrap();
-
-    buffer
+        Ok(())
+    }
 }
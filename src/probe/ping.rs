@@ -1,37 +1,90 @@
 use std::{
+    collections::HashMap,
+    io,
     net::IpAddr,
     num::NonZeroU32,
     str::FromStr,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 
-use axum::extract::Query;
+use anyhow::{
+    bail,
+    Error,
+};
 use prometheus::{
     register_gauge_with_registry,
     register_int_gauge_with_registry,
-    Encoder,
     Registry,
-    TextEncoder,
 };
-use serde::Deserialize;
+use serde::{
+    Deserialize,
+    Serialize,
+};
 use tokio::process::Command;
 
-#[derive(Debug, Deserialize)]
+use crate::probe::{
+    Probe,
+    ProbeError,
+};
+
+mod icmp;
+
+#[derive(Debug, Default, Deserialize)]
 pub(crate) struct Params {
-    target: Target,
+    target: Option<Target>,
     count: Option<NonZeroU32>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Options {
+    pub(crate) mode: Mode,
+
+    /// How long to wait for each reply in native mode.
+    #[serde(with = "humantime_serde")]
+    pub(crate) timeout: Duration,
+
+    /// How long to wait between sending requests in native mode.
+    #[serde(with = "humantime_serde")]
+    pub(crate) interval: Duration,
+
+    /// Targets to ping in agent mode. Their metrics get a `target` label.
+    pub(crate) targets: Vec<Target>,
+
+    /// How many requests to send to each target in agent mode.
+    pub(crate) count: NonZeroU32,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Mode {
+    /// Use native mode and fall back to the `ping` command if icmp sockets
+    /// are not permitted.
+    #[default]
+    Auto,
+
+    /// Send icmp echo requests from callipe-rs itself.
+    Native,
+
+    /// Run the `ping` command and parse its output.
+    Command,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
-enum Target {
+pub(crate) enum Target {
     Addr(IpAddr),
     Hostname(String),
 }
 
 #[derive(Debug)]
-struct Pinger {
+pub(crate) struct Pinger {
     target: Target,
     count: NonZeroU32,
+    options: Options,
 }
 
 #[derive(Debug, Default, PartialEq)]
@@ -47,49 +100,87 @@ struct Ping {
     mdev: Option<f64>,
 }
 
-pub(crate) async fn handler(Query(params): Query<Params>) -> Vec<u8> {
-    let registry = Pinger {
-        target: params.target,
-        count: params.count.unwrap_or(NonZeroU32::new(1).unwrap()),
-    }
-    .run()
-    .await
-    .unwrap();
+impl Probe for Pinger {
+    const NAME: &'static str = "ping";
+    const LABELS: &'static [&'static str] = &["target"];
 
-    let mut buffer = vec![];
-    let encoder = TextEncoder::new();
-    let metric_families = registry.gather();
-    encoder.encode(&metric_families, &mut buffer).unwrap();
+    type Options = Options;
+    type Params = Params;
 
-    buffer
-}
+    fn validate(params: &Params) -> Result<(), ProbeError> {
+        if params.target.is_none() {
+            return Err(ProbeError::BadRequest("target is required".to_string()));
+        }
 
-impl Pinger {
-    async fn run(self) -> Result<Registry, String> {
-        let registry = Registry::new();
+        Ok(())
+    }
 
-        let output = Command::new("ping")
-            .arg("-q")
-            .arg("-c")
-            .arg(format!("{}", self.count))
-            .arg(format!("{}", self.target))
-            .output()
-            .await
-            .unwrap();
+    async fn collect(registry: &Registry, options: &Options, params: Params) -> Result<(), Error> {
+        let Some(target) = params.target else {
+            bail!("no target to ping")
+        };
 
-        register_int_gauge_with_registry!("ping_failed_status", "if the probe failed", registry)
-            .unwrap()
-            .set(output.status.code().unwrap_or_default().into());
+        Pinger {
+            target,
+            count: params.count.unwrap_or(NonZeroU32::MIN),
+            options: options.clone(),
+        }
+        .run(registry)
+        .await
+    }
 
-        let ping = Ping::from_str(&String::from_utf8_lossy(&output.stdout)).unwrap();
+    fn scheduled(options: &Options) -> Vec<(Params, HashMap<String, String>)> {
+        options
+            .targets
+            .iter()
+            .map(|target| {
+                let params = Params {
+                    target: Some(target.clone()),
+                    count: Some(options.count),
+                };
+
+                (
+                    params,
+                    HashMap::from([("target".to_string(), target.to_string())]),
+                )
+            })
+            .collect()
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            mode: Mode::default(),
+            timeout: Duration::from_secs(1),
+            interval: Duration::from_secs(1),
+            targets: Vec::new(),
+            count: NonZeroU32::MIN,
+        }
+    }
+}
+
+impl Pinger {
+    async fn run(self, registry: &Registry) -> Result<(), Error> {
+        let (status, ping) = match self.options.mode {
+            Mode::Command => self.command().await?,
+            Mode::Native => self.native(self.open().await?).await?,
+            Mode::Auto => match self.open().await {
+                Ok(echo) => self.native(echo).await?,
+                Err(err) if err.kind() == io::ErrorKind::PermissionDenied => self.command().await?,
+                Err(err) => return Err(err.into()),
+            },
+        };
+
+        register_int_gauge_with_registry!("ping_failed_status", "if the probe failed", registry)?
+            .set(status.into());
 
         if let Some(transmitted) = ping.transmitted {
             register_int_gauge_with_registry!(
                 "ping_transmitted_count",
                 "how many pings where sent",
                 registry
-            )
-            .unwrap()
+            )?
             .set(transmitted.into());
         }
 
@@ -98,8 +189,7 @@ impl Pinger {
                 "ping_received_count",
                 "how many pings where received",
                 registry
-            )
-            .unwrap()
+            )?
             .set(received.into());
         }
 
@@ -108,8 +198,7 @@ impl Pinger {
                 "ping_errors_count",
                 "how many pings where errors",
                 registry
-            )
-            .unwrap()
+            )?
             .set(errors.into());
         }
 
@@ -118,8 +207,7 @@ impl Pinger {
                 "ping_packetloss_precent",
                 "percentage of lost pings",
                 registry
-            )
-            .unwrap()
+            )?
             .set(packet_loss);
         }
 
@@ -128,8 +216,7 @@ impl Pinger {
                 "ping_time_milliseconds",
                 "how long pinging took in total",
                 registry
-            )
-            .unwrap()
+            )?
             .set(time.into());
         }
 
@@ -138,8 +225,7 @@ impl Pinger {
                 "ping_min_milliseconds",
                 "minimum duration of pings",
                 registry
-            )
-            .unwrap()
+            )?
             .set(min);
         }
 
@@ -148,8 +234,7 @@ impl Pinger {
                 "ping_avg_milliseconds",
                 "average duration of pings",
                 registry
-            )
-            .unwrap()
+            )?
             .set(avg);
         }
 
@@ -158,8 +243,7 @@ impl Pinger {
                 "ping_max_milliseconds",
                 "maximum duration of pings",
                 registry
-            )
-            .unwrap()
+            )?
             .set(max);
         }
 
@@ -168,12 +252,93 @@ impl Pinger {
                 "ping_mdev_milliseconds",
                 "standard deviation of pings",
                 registry
-            )
-            .unwrap()
+            )?
             .set(mdev);
         }
 
-        Ok(registry)
+        Ok(())
+    }
+
+    /// Runs the `ping` command and returns its exit code and parsed output.
+    async fn command(&self) -> Result<(i32, Ping), Error> {
+        let output = Command::new("ping")
+            .arg("-q")
+            .arg("-c")
+            .arg(format!("{}", self.count))
+            .arg(format!("{}", self.target))
+            .output()
+            .await?;
+
+        let ping = Ping::from_str(&String::from_utf8_lossy(&output.stdout)).map_err(Error::msg)?;
+
+        Ok((output.status.code().unwrap_or_default(), ping))
+    }
+
+    async fn open(&self) -> io::Result<icmp::Echo> {
+        icmp::Echo::open(self.target.resolve().await?)
+    }
+
+    /// Pings with `echo` and returns a status like the exit code of `ping`.
+    async fn native(&self, echo: icmp::Echo) -> Result<(i32, Ping), Error> {
+        let start = Instant::now();
+        let mut replies = Vec::new();
+        let mut errors = 0;
+
+        for sequence in 1..=self.count.get() {
+            if sequence > 1 {
+                tokio::time::sleep(self.options.interval).await;
+            }
+
+            // The sequence number wraps around like in `ping`.
+            #[allow(clippy::cast_possible_truncation)]
+            match echo.ping(sequence as u16, self.options.timeout).await? {
+                icmp::Reply::Echo(rtt) => replies.push(rtt),
+                icmp::Reply::Error => errors += 1,
+                icmp::Reply::Timeout => {}
+            }
+        }
+
+        let ping = Ping::from_replies(self.count.get(), errors, &replies, start.elapsed());
+        let status = i32::from(replies.is_empty());
+
+        Ok((status, ping))
+    }
+}
+
+impl Ping {
+    /// Builds the summary `ping` would print from the round trip times.
+    #[allow(clippy::cast_precision_loss)]
+    fn from_replies(transmitted: u32, errors: u32, replies: &[Duration], time: Duration) -> Self {
+        let received = u32::try_from(replies.len()).unwrap_or(u32::MAX);
+
+        let mut out = Self {
+            transmitted: Some(transmitted),
+            received: Some(received),
+            errors: (errors > 0).then_some(errors),
+            packet_loss: Some(f64::from(transmitted - received) / f64::from(transmitted) * 100.0),
+            time: Some(u32::try_from(time.as_millis()).unwrap_or(u32::MAX)),
+            ..Self::default()
+        };
+
+        if replies.is_empty() {
+            return out;
+        }
+
+        let millis = replies
+            .iter()
+            .map(|rtt| rtt.as_secs_f64() * 1000.0)
+            .collect::<Vec<_>>();
+
+        let count = millis.len() as f64;
+        let avg = millis.iter().sum::<f64>() / count;
+        let square_avg = millis.iter().map(|v| v * v).sum::<f64>() / count;
+
+        out.min = millis.iter().copied().reduce(f64::min);
+        out.max = millis.iter().copied().reduce(f64::max);
+        out.avg = Some(avg);
+        out.mdev = Some((square_avg - avg * avg).max(0.0).sqrt());
+
+        out
     }
 }
 
@@ -187,45 +352,43 @@ impl std::str::FromStr for Ping {
             let split = line.split_ascii_whitespace().collect::<Vec<_>>();
 
             match split.as_slice() {
-                []
-                | ["PING" | "PING6(56=40+8+8", ..]
-                | ["---", _, "ping" | "ping6", "statistics", "---"] => {}
-
                 [transmitted, "packets", "transmitted,", received, "packets", "received,", packet_loss, "packet", "loss"] =>
                 {
-                    out.transmitted = Some(transmitted.parse().unwrap());
-                    out.received = Some(received.parse().unwrap());
-                    out.packet_loss = Some(packet_loss.trim_end_matches('%').parse().unwrap());
+                    out.transmitted = Some(number(transmitted)?);
+                    out.received = Some(number(received)?);
+                    out.packet_loss = Some(number(packet_loss.trim_end_matches('%'))?);
                 }
 
                 [transmitted, "packets", "transmitted,", received, "received,", packet_loss, "packet", "loss,", "time", time] =>
                 {
-                    out.transmitted = Some(transmitted.parse().unwrap());
-                    out.received = Some(received.parse().unwrap());
-                    out.packet_loss = Some(packet_loss.trim_end_matches('%').parse().unwrap());
-                    out.time = Some(time.trim_end_matches("ms").parse().unwrap());
+                    out.transmitted = Some(number(transmitted)?);
+                    out.received = Some(number(received)?);
+                    out.packet_loss = Some(number(packet_loss.trim_end_matches('%'))?);
+                    out.time = Some(number(time.trim_end_matches("ms"))?);
                 }
 
                 [transmitted, "packets", "transmitted,", received, "received,", errors, "errors,", packet_loss, "packet", "loss,", "time", time] =>
                 {
-                    out.transmitted = Some(transmitted.parse().unwrap());
-                    out.received = Some(received.parse().unwrap());
-                    out.errors = Some(errors.trim_start_matches('+').parse().unwrap());
-                    out.packet_loss = Some(packet_loss.trim_end_matches('%').parse().unwrap());
-                    out.time = Some(time.trim_end_matches("ms").parse().unwrap());
+                    out.transmitted = Some(number(transmitted)?);
+                    out.received = Some(number(received)?);
+                    out.errors = Some(number(errors.trim_start_matches('+'))?);
+                    out.packet_loss = Some(number(packet_loss.trim_end_matches('%'))?);
+                    out.time = Some(number(time.trim_end_matches("ms"))?);
                 }
 
                 ["rtt", "min/avg/max/mdev", "=", data, "ms"]
                 | ["round-trip", "min/avg/max/stddev" | "min/avg/max/std-dev", "=", data, "ms"] => {
                     let mut split = data.split('/');
 
-                    out.min = split.next().map(str::parse).transpose().unwrap();
-                    out.avg = split.next().map(str::parse).transpose().unwrap();
-                    out.max = split.next().map(str::parse).transpose().unwrap();
-                    out.mdev = split.next().map(str::parse).transpose().unwrap();
+                    out.min = split.next().map(number).transpose()?;
+                    out.avg = split.next().map(number).transpose()?;
+                    out.max = split.next().map(number).transpose()?;
+                    out.mdev = split.next().map(number).transpose()?;
                 }
 
-                other => todo!("{other:?}"),
+                // Headers, warnings and per packet lines are not needed for
+                // the summary.
+                _ => {}
             }
         }
 
@@ -233,6 +396,31 @@ impl std::str::FromStr for Ping {
     }
 }
 
+fn number<T>(value: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|err| format!("can not parse {value:?} from ping output: {err}"))
+}
+
+impl Target {
+    async fn resolve(&self) -> io::Result<IpAddr> {
+        match self {
+            Self::Addr(v) => Ok(*v),
+            Self::Hostname(v) => tokio::net::lookup_host((v.as_str(), 0))
+                .await?
+                .next()
+                .map(|addr| addr.ip())
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, format!("no address for {v}"))
+                }),
+        }
+    }
+}
+
 impl std::fmt::Display for Target {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -244,6 +432,7 @@ impl std::fmt::Display for Target {
 
 #[cfg(test)]
 mod tests {
+    #[allow(clippy::needless_raw_string_hashes)]
     mod parse {
         use pretty_assertions::assert_eq;
         use std::str::FromStr;
@@ -274,8 +463,6 @@ rtt min/avg/max/mdev = 7.537/7.537/7.537/0.000 ms"#;
 
             let got = Ping::from_str(INPUT).unwrap();
 
-            dbg!(&got);
-
             assert_eq!(expected, got);
         }
 
@@ -303,8 +490,6 @@ rtt min/avg/max/mdev = 7.427/7.654/7.936/0.169 ms"#;
 
             let got = Ping::from_str(INPUT).unwrap();
 
-            dbg!(&got);
-
             assert_eq!(expected, got);
         }
 
@@ -333,7 +518,85 @@ PING 51.61.61.1 (51.61.61.1) 56(84) bytes of data.
 
             let got = Ping::from_str(INPUT).unwrap();
 
-            dbg!(&got);
+            assert_eq!(expected, got);
+        }
+
+        #[test]
+        fn broadcast_warning() {
+            // ping -q -b -c 1 255.255.255.255
+            const INPUT: &str = r"
+WARNING: pinging broadcast address
+PING 255.255.255.255 (255.255.255.255) 56(84) bytes of data.
+
+--- 255.255.255.255 ping statistics ---
+1 packets transmitted, 0 received, 100% packet loss, time 0ms
+
+";
+
+            let expected = Ping {
+                transmitted: Some(1),
+                received: Some(0),
+                packet_loss: Some(100.0),
+                time: Some(0),
+                ..Ping::default()
+            };
+
+            let got = Ping::from_str(INPUT).unwrap();
+
+            assert_eq!(expected, got);
+        }
+
+        #[test]
+        fn invalid_number() {
+            const INPUT: &str = "x packets transmitted, 0 received, 100% packet loss, time 0ms";
+
+            assert!(Ping::from_str(INPUT).is_err());
+        }
+    }
+
+    mod replies {
+        use pretty_assertions::assert_eq;
+        use std::time::Duration;
+
+        use crate::probe::ping::Ping;
+
+        #[test]
+        fn all_received() {
+            let replies = [
+                Duration::from_millis(10),
+                Duration::from_millis(20),
+                Duration::from_millis(30),
+            ];
+
+            let expected = Ping {
+                transmitted: Some(3),
+                received: Some(3),
+                errors: None,
+                packet_loss: Some(0.0),
+                time: Some(2030),
+                min: Some(10.0),
+                avg: Some(20.0),
+                max: Some(30.0),
+                mdev: Some(8.164_965_809_277_26),
+            };
+
+            let got = Ping::from_replies(3, 0, &replies, Duration::from_millis(2030));
+
+            assert_eq!(expected, got);
+        }
+
+        #[test]
+        fn nothing_received() {
+            let expected = Ping {
+                transmitted: Some(4),
+                received: Some(0),
+                errors: Some(2),
+                packet_loss: Some(100.0),
+                time: Some(7000),
+                ..Ping::default()
+            };
+
+            let got = Ping::from_replies(4, 2, &[], Duration::from_secs(7));
 
             assert_eq!(expected, got);
         }
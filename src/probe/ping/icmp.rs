@@ -0,0 +1,393 @@
+use std::{
+    io,
+    net::{
+        IpAddr,
+        SocketAddr,
+        UdpSocket as StdUdpSocket,
+    },
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use socket2::{
+    Domain,
+    Protocol,
+    Socket,
+    Type,
+};
+use tokio::net::UdpSocket;
+
+/// Size of the echo payload, same as the default of `ping`.
+const PAYLOAD_SIZE: usize = 56;
+
+const ECHO_REQUEST_V4: u8 = 8;
+const ECHO_REPLY_V4: u8 = 0;
+const DESTINATION_UNREACHABLE_V4: u8 = 3;
+const TIME_EXCEEDED_V4: u8 = 11;
+
+const ECHO_REQUEST_V6: u8 = 128;
+const ECHO_REPLY_V6: u8 = 129;
+const DESTINATION_UNREACHABLE_V6: u8 = 1;
+const TIME_EXCEEDED_V6: u8 = 3;
+
+/// Size of the ipv6 header that is embedded in icmpv6 error messages.
+const IPV6_HEADER_SIZE: usize = 40;
+
+/// Sends icmp echo requests to a single address.
+///
+/// Prefers unprivileged datagram sockets (`net.ipv4.ping_group_range` on
+/// linux) and falls back to raw sockets which need `CAP_NET_RAW`.
+#[derive(Debug)]
+pub(super) struct Echo {
+    socket: UdpSocket,
+    kind: Type,
+    target: SocketAddr,
+    identifier: u16,
+    token: [u8; 8],
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) enum Reply {
+    /// Got an echo reply after the duration.
+    Echo(Duration),
+    /// Got an icmp error like destination unreachable for the request.
+    Error,
+    /// Got no answer in time.
+    Timeout,
+}
+
+/// Interesting parts of a received icmp message.
+#[derive(Debug, PartialEq)]
+struct Message<'a> {
+    kind: MessageKind,
+    identifier: u16,
+    sequence: u16,
+    payload: &'a [u8],
+}
+
+#[derive(Debug, PartialEq)]
+enum MessageKind {
+    Reply,
+    Error,
+}
+
+impl Echo {
+    pub(super) fn open(addr: IpAddr) -> io::Result<Self> {
+        let (domain, protocol) = match addr {
+            IpAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4),
+            IpAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6),
+        };
+
+        let (socket, kind) = match Socket::new(domain, Type::DGRAM, Some(protocol)) {
+            Ok(socket) => (socket, Type::DGRAM),
+            Err(_) => (Socket::new(domain, Type::RAW, Some(protocol))?, Type::RAW),
+        };
+
+        socket.set_nonblocking(true)?;
+
+        // Not used for udp, only to get async send_to and recv_from for the
+        // icmp socket.
+        let socket = UdpSocket::from_std(StdUdpSocket::from(socket))?;
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+
+        #[allow(clippy::cast_possible_truncation)]
+        Ok(Self {
+            socket,
+            kind,
+            target: SocketAddr::new(addr, 0),
+            identifier: (std::process::id() as u16) ^ (nanos as u16),
+            token: (nanos as u64).to_be_bytes(),
+        })
+    }
+
+    /// Sends one echo request and waits up to `timeout` for the answer.
+    pub(super) async fn ping(&self, sequence: u16, timeout: Duration) -> io::Result<Reply> {
+        let request = echo_request(
+            self.target.is_ipv6(),
+            self.identifier,
+            sequence,
+            &self.token,
+        );
+
+        let start = Instant::now();
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        if let Err(err) = self.socket.send_to(&request, self.target).await {
+            return if is_unreachable(&err) {
+                Ok(Reply::Error)
+            } else {
+                Err(err)
+            };
+        }
+
+        let mut buffer = [0; 1500];
+
+        loop {
+            let (received, from) =
+                match tokio::time::timeout_at(deadline, self.socket.recv_from(&mut buffer)).await {
+                    Err(_) => return Ok(Reply::Timeout),
+                    Ok(Err(err)) if is_unreachable(&err) => return Ok(Reply::Error),
+                    Ok(Err(err)) => return Err(err),
+                    Ok(Ok(received)) => received,
+                };
+
+            let Some(message) = parse(
+                self.target.is_ipv6(),
+                self.kind == Type::RAW,
+                &buffer[..received],
+            ) else {
+                continue;
+            };
+
+            // The kernel replaces the identifier with its own for datagram
+            // sockets and only delivers replies for it.
+            let identifier_matches =
+                self.kind == Type::DGRAM || message.identifier == self.identifier;
+
+            if !identifier_matches || message.sequence != sequence {
+                continue;
+            }
+
+            match message.kind {
+                // Raw sockets get all icmp traffic of the host. Errors come
+                // from routers instead of the target but replies can not.
+                MessageKind::Reply if from.ip() != self.target.ip() => {}
+                // Replies to other pingers with a colliding identifier.
+                MessageKind::Reply if !message.payload.starts_with(&self.token) => {}
+                MessageKind::Reply => return Ok(Reply::Echo(start.elapsed())),
+                MessageKind::Error => return Ok(Reply::Error),
+            }
+        }
+    }
+}
+
+fn is_unreachable(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable
+    )
+}
+
+/// Internet checksum as described in RFC 1071.
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|chunk| u32::from(u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)])))
+        .sum::<u32>();
+
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    !(sum as u16)
+}
+
+fn echo_request(ipv6: bool, identifier: u16, sequence: u16, token: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(8 + PAYLOAD_SIZE);
+
+    packet.push(if ipv6 {
+        ECHO_REQUEST_V6
+    } else {
+        ECHO_REQUEST_V4
+    });
+    packet.push(0);
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(&identifier.to_be_bytes());
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend_from_slice(token);
+
+    #[allow(clippy::cast_possible_truncation)]
+    packet.extend((packet.len()..8 + PAYLOAD_SIZE).map(|index| index as u8));
+
+    // The kernel calculates the checksum for icmpv6 as it needs the ip
+    // addresses of the pseudo header.
+    if !ipv6 {
+        let sum = checksum(&packet);
+        packet[2..4].copy_from_slice(&sum.to_be_bytes());
+    }
+
+    packet
+}
+
+/// Parses an icmp message. Raw ipv4 sockets also receive the ip header which
+/// is skipped.
+fn parse(ipv6: bool, raw: bool, packet: &[u8]) -> Option<Message<'_>> {
+    let packet = if raw && !ipv6 {
+        let header = usize::from(packet.first()? & 0x0f) * 4;
+        packet.get(header..)?
+    } else {
+        packet
+    };
+
+    let kind = match (ipv6, *packet.first()?) {
+        (false, ECHO_REPLY_V4) | (true, ECHO_REPLY_V6) => MessageKind::Reply,
+
+        (false, DESTINATION_UNREACHABLE_V4 | TIME_EXCEEDED_V4)
+        | (true, DESTINATION_UNREACHABLE_V6 | TIME_EXCEEDED_V6) => MessageKind::Error,
+
+        _ => return None,
+    };
+
+    let echo = match kind {
+        MessageKind::Reply => packet,
+
+        // Errors contain the ip header and the start of the original request.
+        MessageKind::Error => {
+            let original = packet.get(8..)?;
+
+            let header = if ipv6 {
+                IPV6_HEADER_SIZE
+            } else {
+                usize::from(original.first()? & 0x0f) * 4
+            };
+
+            let request = original.get(header..)?;
+
+            let expected = if ipv6 {
+                ECHO_REQUEST_V6
+            } else {
+                ECHO_REQUEST_V4
+            };
+            if *request.first()? != expected {
+                return None;
+            }
+
+            request
+        }
+    };
+
+    Some(Message {
+        kind,
+        identifier: u16::from_be_bytes([*echo.get(4)?, *echo.get(5)?]),
+        sequence: u16::from_be_bytes([*echo.get(6)?, *echo.get(7)?]),
+        payload: echo.get(8..).unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use std::{
+        io,
+        net::{
+            IpAddr,
+            Ipv4Addr,
+            Ipv6Addr,
+        },
+        time::Duration,
+    };
+
+    use crate::probe::ping::icmp::{
+        checksum,
+        echo_request,
+        parse,
+        Echo,
+        Message,
+        MessageKind,
+        Reply,
+    };
+
+    #[test]
+    fn checksum_rfc1071() {
+        // Example from RFC 1071 section 3.
+        const INPUT: [u8; 8] = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+
+        assert_eq!(!0xddf2, checksum(&INPUT));
+    }
+
+    #[test]
+    fn checksum_odd_length() {
+        assert_eq!(!0x0100, checksum(&[0x01]));
+    }
+
+    #[test]
+    fn request_v4() {
+        let got = echo_request(false, 0x1234, 7, b"callipe!");
+
+        assert_eq!(64, got.len());
+        assert_eq!([8, 0], got[..2]);
+        assert_eq!([0x12, 0x34, 0x00, 0x07], got[4..8]);
+        assert_eq!(b"callipe!", &got[8..16]);
+        assert_eq!(0, checksum(&got));
+    }
+
+    #[test]
+    fn parse_reply_v4_raw() {
+        let mut request = echo_request(false, 0x1234, 7, b"callipe!");
+        request[0] = 0;
+
+        // Minimal ipv4 header with ihl of 5.
+        let mut packet = vec![0x45];
+        packet.extend_from_slice(&[0; 19]);
+        packet.extend_from_slice(&request);
+
+        let expected = Message {
+            kind: MessageKind::Reply,
+            identifier: 0x1234,
+            sequence: 7,
+            payload: &request[8..],
+        };
+
+        let got = parse(false, true, &packet).unwrap();
+
+        assert_eq!(expected, got);
+    }
+
+    #[test]
+    fn parse_error_v6() {
+        let request = echo_request(true, 0x1234, 9, b"callipe!");
+
+        let mut packet = vec![1, 0, 0, 0, 0, 0, 0, 0];
+        packet.extend_from_slice(&[0x60; 40]);
+        packet.extend_from_slice(&request);
+
+        let got = parse(true, false, &packet).unwrap();
+
+        assert_eq!(MessageKind::Error, got.kind);
+        assert_eq!(0x1234, got.identifier);
+        assert_eq!(9, got.sequence);
+    }
+
+    #[test]
+    fn parse_ignores_requests() {
+        let request = echo_request(false, 0x1234, 7, b"callipe!");
+
+        assert_eq!(None, parse(false, false, &request));
+    }
+
+    async fn ping_loopback(addr: IpAddr) {
+        let echo = match Echo::open(addr) {
+            Ok(echo) => echo,
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+                eprintln!("skipping, not allowed to open icmp sockets: {err}");
+                return;
+            }
+            Err(err) => panic!("{err}"),
+        };
+
+        for sequence in 1..=3 {
+            let got = echo.ping(sequence, Duration::from_secs(1)).await.unwrap();
+
+            assert!(matches!(got, Reply::Echo(_)), "{got:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn loopback_v4() {
+        ping_loopback(Ipv4Addr::LOCALHOST.into()).await;
+    }
+
+    #[tokio::test]
+    async fn loopback_v6() {
+        ping_loopback(Ipv6Addr::LOCALHOST.into()).await;
+    }
+}
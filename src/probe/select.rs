@@ -0,0 +1,113 @@
+use axum::{
+    async_trait,
+    extract::{
+        FromRequestParts,
+        Query,
+    },
+    http::request::Parts,
+};
+
+use crate::probe::ProbeError;
+
+/// Metric families a request asked for with `name[]=<name>`. Every family is
+/// returned if there are none.
+#[derive(Debug, Default)]
+pub(crate) struct Names(Vec<String>);
+
+/// Probes a request to the aggregate system endpoint asked for with
+/// `collect[]=<probe>`. Every configured probe runs if there are none.
+#[derive(Debug, Default)]
+pub(crate) struct Probes(Vec<String>);
+
+impl Names {
+    pub(crate) fn is_match(&self, name: &str) -> bool {
+        self.0.is_empty() || self.0.iter().any(|wanted| wanted == name)
+    }
+}
+
+impl Probes {
+    pub(crate) fn is_match(&self, probe: &str) -> bool {
+        self.0.is_empty() || self.0.iter().any(|wanted| wanted == probe)
+    }
+
+    /// Returns the first requested probe that is not in `configured`.
+    pub(crate) fn unknown<'a>(&'a self, configured: &[&str]) -> Option<&'a str> {
+        self.0
+            .iter()
+            .find(|wanted| !configured.contains(&wanted.as_str()))
+            .map(String::as_str)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Names
+where
+    S: Send + Sync,
+{
+    type Rejection = ProbeError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(repeated(parts, state, "name").await?))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Probes
+where
+    S: Send + Sync,
+{
+    type Rejection = ProbeError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(repeated(parts, state, "collect").await?))
+    }
+}
+
+/// Values of the query parameter `key` which can be given more than once as
+/// `key[]=a&key[]=b` or `key=a&key=b`.
+async fn repeated<S>(parts: &mut Parts, state: &S, key: &str) -> Result<Vec<String>, ProbeError>
+where
+    S: Send + Sync,
+{
+    let Query(query) = Query::<Vec<(String, String)>>::from_request_parts(parts, state)
+        .await
+        .map_err(|err| ProbeError::BadRequest(err.body_text()))?;
+
+    Ok(query
+        .into_iter()
+        .filter(|(name, _)| name.strip_suffix("[]").unwrap_or(name) == key)
+        .map(|(_, value)| value)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    mod repeated {
+        use axum::{
+            extract::FromRequestParts,
+            http::Request,
+        };
+        use pretty_assertions::assert_eq;
+
+        use crate::probe::select::{
+            Names,
+            Probes,
+        };
+
+        #[tokio::test]
+        async fn brackets_and_plain() {
+            let (mut parts, ()) = Request::get(
+                "/probe/system?collect[]=cpu&collect%5B%5D=memory&name=system_load_1&format=json",
+            )
+            .body(())
+            .unwrap()
+            .into_parts();
+
+            let Probes(probes) = Probes::from_request_parts(&mut parts, &()).await.unwrap();
+            let Names(names) = Names::from_request_parts(&mut parts, &()).await.unwrap();
+
+            assert_eq!(vec!["cpu", "memory"], probes);
+            assert_eq!(vec!["system_load_1"], names);
+        }
+    }
+}
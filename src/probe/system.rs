@@ -1,32 +1,137 @@
-use axum::extract::Query;
-use prometheus::{
-    Encoder,
-    Registry,
-    TextEncoder,
+use std::{
+    fmt,
+    sync::Arc,
+};
+
+use axum::{
+    response::Response,
+    routing::get,
+    Extension,
+    Router,
+};
+
+use crate::probe::{
+    self,
+    filter::Filter,
+    format::Format,
+    select::{
+        Names,
+        Probes,
+    },
+    AnyProbe,
+    Collector,
+    Labels,
+    Probe,
+    ProbeError,
 };
-use serde::Deserialize;
 
 pub(crate) mod cpu;
+pub(crate) mod disk;
+pub(crate) mod filesystem;
 pub(crate) mod load;
 pub(crate) mod memory;
+pub(crate) mod netstat;
+pub(crate) mod network;
 pub(crate) mod swap;
 
-#[derive(Debug, Deserialize)]
-pub(crate) struct Params {}
+/// The probes that are enabled in the config with their options. Used by the
+/// aggregate handler to only run what was configured.
+#[derive(Clone, Default)]
+pub(crate) struct Options {
+    probes: Vec<Arc<dyn AnyProbe>>,
+}
+
+/// Routes of the configured system probes and the aggregate endpoint that
+/// runs all of them.
+#[derive(Debug, Default)]
+pub(crate) struct Routes {
+    router: Router,
+    options: Options,
+}
+
+/// Runs every configured probe or the ones selected with `collect[]`. A
+/// failing probe does not fail the request, the metrics of the other probes
+/// are still returned.
+pub(crate) async fn handler(
+    Extension(options): Extension<Options>,
+    probes: Probes,
+    names: Names,
+    format: Format,
+) -> Result<Response, ProbeError> {
+    let configured = options
+        .probes
+        .iter()
+        .map(|probe| probe.name())
+        .collect::<Vec<_>>();
+
+    if let Some(unknown) = probes.unknown(&configured) {
+        return Err(ProbeError::BadRequest(format!(
+            "probe {unknown} is not configured, configured are: {}",
+            configured.join(", ")
+        )));
+    }
 
-#[allow(clippy::unused_async)]
-pub(crate) async fn handler(Query(_params): Query<Params>) -> Vec<u8> {
-    let registry = Registry::new();
-    load::Load::run(&registry).unwrap();
-    cpu::Cpu::run(&registry).unwrap();
-    memory::Memory::run(&registry).unwrap();
-    // TODO: Not working on freebsd
-    // swap::Swap::run(&registry).unwrap();
+    let mut collector = Collector::new(names);
 
-    let mut buffer = vec![];
-    let encoder = TextEncoder::new();
-    let metric_families = registry.gather();
-    encoder.encode(&metric_families, &mut buffer).unwrap();
+    for probe in &options.probes {
+        if probes.is_match(probe.name()) {
+            probe.collect(&mut collector).await;
+        }
+    }
+
+    collector.finish(format)
+}
+
+impl Routes {
+    /// Serves `P` on its own route and adds it to the aggregate endpoint.
+    /// Only the metric families that pass `metrics` are returned and `labels`
+    /// are added to them.
+    pub(crate) fn add<P: Probe>(
+        &mut self,
+        options: &P::Options,
+        labels: &Labels,
+        metrics: &Filter,
+    ) {
+        self.options
+            .add::<P>(options.clone(), labels.clone(), metrics.clone());
+        self.router = probe::route::<P>(
+            std::mem::take(&mut self.router),
+            options.clone(),
+            labels.clone(),
+            metrics.clone(),
+        );
+    }
+
+    /// Returns `None` if no system probe was added.
+    pub(crate) fn into_router(self) -> Option<Router> {
+        if self.options.is_empty() {
+            return None;
+        }
+
+        Some(
+            self.router
+                .route("/", get(handler).layer(Extension(self.options))),
+        )
+    }
+}
+
+impl Options {
+    /// Adds `P` with `options` to the probes run by the aggregate handler.
+    /// The probe runs with its default parameters.
+    fn add<P: Probe>(&mut self, options: P::Options, labels: Labels, metrics: Filter) {
+        self.probes
+            .push(probe::configured::<P>(options, labels, metrics));
+    }
+
+    fn is_empty(&self) -> bool {
+        self.probes.is_empty()
+    }
+}
 
-    buffer
+impl fmt::Debug for Options {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.probes.iter().map(|probe| probe.name()))
+            .finish()
+    }
 }
@@ -1,75 +1,735 @@
+#[cfg(target_os = "linux")]
+use std::sync::LazyLock;
+use std::{
+    str::FromStr,
+    time::Duration,
+};
+
 use anyhow::Error;
-use axum::extract::Query;
 use prometheus::{
-    register_int_counter_with_registry,
+    register_counter_vec_with_registry,
+    register_gauge_vec_with_registry,
+    register_int_counter_vec_with_registry,
     register_int_gauge_with_registry,
-    Encoder,
+    IntCounterVec,
     Registry,
-    TextEncoder,
 };
-use serde::Deserialize;
+use serde::{
+    Deserialize,
+    Serialize,
+};
 use systemstat::{
+    CpuTime,
     Platform,
     System,
 };
 
-#[derive(Debug, Deserialize)]
-pub(crate) struct Params {}
+use crate::probe::{
+    Probe,
+    ProbeError,
+};
+
+/// Overrides the configured options for a single request.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct Params {
+    per_cpu: Option<bool>,
+    total_cpu: Option<bool>,
+
+    /// Take two snapshots this far apart and export the utilization in
+    /// percent in between instead of the counters.
+    #[serde(default, with = "humantime_serde")]
+    sample: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Options {
+    /// Export the counters for every cpu with the cpu index as the `cpu`
+    /// label.
+    pub(crate) per_cpu: bool,
+
+    /// Export the counters aggregated over all cpus with `total` as the `cpu`
+    /// label.
+    pub(crate) total_cpu: bool,
+
+    /// Also export the per mode tick counters (`system_cpu_user`, ...) that
+    /// were exported before `system_cpu_seconds_total`. Defaults to true
+    /// where there is no `/proc/stat` to read `system_cpu_seconds_total` from.
+    pub(crate) legacy_counters: bool,
+}
+
+/// Clock ticks per second used in `/proc/stat`. Falls back to 100 which the
+/// kernel uses on all common architectures.
+#[cfg(target_os = "linux")]
+#[allow(clippy::cast_precision_loss)]
+static USER_HZ: LazyLock<f64> = LazyLock::new(|| {
+    nix::unistd::sysconf(nix::unistd::SysconfVar::CLK_TCK)
+        .ok()
+        .flatten()
+        .map_or(100.0, |hz| hz as f64)
+});
+
+/// Upper bound for the `sample` parameter so a request can not keep a
+/// connection open forever.
+const MAX_SAMPLE: Duration = Duration::from_secs(30);
+
+/// One `cpu` line of `/proc/stat` in ticks. `cpu` is `None` for the line
+/// aggregated over all cpus.
+#[derive(Debug, Default, PartialEq)]
+struct CpuStat {
+    cpu: Option<usize>,
+    user: u64,
+    nice: u64,
+    system: u64,
+    idle: u64,
+    iowait: u64,
+    irq: u64,
+    softirq: u64,
+    steal: u64,
+    guest: u64,
+    guest_nice: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct Cpu {}
 
 #[derive(Debug)]
-pub(super) struct Cpu {}
+struct Counters {
+    user: IntCounterVec,
+    nice: IntCounterVec,
+    system: IntCounterVec,
+    irq: IntCounterVec,
+    idle: IntCounterVec,
+    other: IntCounterVec,
+}
 
-#[allow(clippy::unused_async)]
-pub(crate) async fn handler(Query(_params): Query<Params>) -> Vec<u8> {
-    let registry = Registry::new();
-    Cpu::run(&registry).unwrap();
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            per_cpu: false,
+            total_cpu: true,
+            legacy_counters: !std::path::Path::new("/proc/stat").exists(),
+        }
+    }
+}
 
-    let mut buffer = vec![];
-    let encoder = TextEncoder::new();
-    let metric_families = registry.gather();
-    encoder.encode(&metric_families, &mut buffer).unwrap();
+impl Probe for Cpu {
+    const NAME: &'static str = "cpu";
+    const LABELS: &'static [&'static str] = &["cpu", "mode"];
 
-    buffer
+    type Options = Options;
+    type Params = Params;
+
+    fn validate(params: &Params) -> Result<(), ProbeError> {
+        match params.sample {
+            Some(sample) if sample > MAX_SAMPLE => Err(ProbeError::BadRequest(format!(
+                "sample can not be longer than {}",
+                humantime::format_duration(MAX_SAMPLE)
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    async fn collect(registry: &Registry, options: &Options, params: Params) -> Result<(), Error> {
+        let options = options.with_params(&params);
+
+        match params.sample {
+            Some(sample) => Self::sample(registry, &options, sample).await,
+            None => Self::run(registry, &options),
+        }
+    }
+}
+
+impl Options {
+    fn with_params(&self, params: &Params) -> Self {
+        Self {
+            per_cpu: params.per_cpu.unwrap_or(self.per_cpu),
+            total_cpu: params.total_cpu.unwrap_or(self.total_cpu),
+            legacy_counters: self.legacy_counters,
+        }
+    }
 }
 
 impl Cpu {
-    pub(super) fn run(registry: &Registry) -> Result<(), Error> {
-        let sys = System::new();
-        let cpu = sys.cpu_time_aggregate()?;
+    fn run(registry: &Registry, options: &Options) -> Result<(), Error> {
+        Self::seconds(registry, options)?;
 
-        register_int_counter_with_registry!("system_cpu_user", "system cpu user usage", registry)?
-            .inc_by(cpu.user.try_into().unwrap());
+        if options.legacy_counters && (options.per_cpu || options.total_cpu) {
+            let sys = System::new();
+            let counters = Counters::register(registry)?;
 
-        register_int_counter_with_registry!("system_cpu_nice", "system cpu nice usage", registry)?
-            .inc_by(cpu.nice.try_into().unwrap());
+            if options.total_cpu {
+                counters.inc("total", &sys.cpu_time_aggregate()?)?;
+            }
 
-        register_int_counter_with_registry!(
-            "system_cpu_system",
-            "system cpu system usage",
+            if options.per_cpu {
+                for (index, cpu) in sys.cpu_time()?.iter().enumerate() {
+                    counters.inc(&index.to_string(), cpu)?;
+                }
+            }
+        }
+
+        #[allow(clippy::cast_possible_wrap)]
+        register_int_gauge_with_registry!(
+            "system_cpu_core_count",
+            "how many cpus are available",
             registry
         )?
-        .inc_by(cpu.system.try_into().unwrap());
+        .set(num_cpus::get() as i64);
 
-        register_int_counter_with_registry!("system_cpu_irq", "system cpu irq usage", registry)?
-            .inc_by(cpu.interrupt.try_into().unwrap());
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn seconds(registry: &Registry, options: &Options) -> Result<(), Error> {
+        if !options.per_cpu && !options.total_cpu {
+            return Ok(());
+        }
 
-        register_int_counter_with_registry!("system_cpu_idle", "system cpu idle usage", registry)?
-            .inc_by(cpu.idle.try_into().unwrap());
+        let stats = CpuStat::parse_all(&std::fs::read_to_string("/proc/stat")?)?;
 
-        register_int_counter_with_registry!(
-            "system_cpu_other",
-            "system cpu other usage",
+        CpuStat::register(registry, options, &stats, *USER_HZ)
+    }
+
+    /// The per mode breakdown is read from `/proc/stat` which only exists on
+    /// linux.
+    #[cfg(not(target_os = "linux"))]
+    #[allow(clippy::unnecessary_wraps)]
+    fn seconds(_registry: &Registry, _options: &Options) -> Result<(), Error> {
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn sample(
+        registry: &Registry,
+        options: &Options,
+        duration: Duration,
+    ) -> Result<(), Error> {
+        let before = CpuStat::parse_all(&tokio::fs::read_to_string("/proc/stat").await?)?;
+        tokio::time::sleep(duration).await;
+        let after = CpuStat::parse_all(&tokio::fs::read_to_string("/proc/stat").await?)?;
+
+        let utilization = register_gauge_vec_with_registry!(
+            "system_cpu_utilization_percent",
+            "percentage of time the cpus spent in each mode during the sample",
+            &["cpu", "mode"],
             registry
-        )?
-        .inc_by(cpu.other.try_into().unwrap());
+        )?;
+
+        for stat in &after {
+            let cpu = match stat.cpu {
+                None if options.total_cpu => "total".to_string(),
+                Some(index) if options.per_cpu => index.to_string(),
+                _ => continue,
+            };
+
+            // Cpus that came online during the sample have no baseline.
+            let Some(previous) = before.iter().find(|previous| previous.cpu == stat.cpu) else {
+                continue;
+            };
+
+            for (mode, percent) in stat.utilization(previous) {
+                utilization.with_label_values(&[&cpu, mode]).set(percent);
+            }
+        }
 
         register_int_gauge_with_registry!(
             "system_cpu_core_count",
             "how many cpus are available",
             registry
         )?
-        .set(num_cpus::get() as i64);
+        .set(i64::try_from(num_cpus::get())?);
 
         Ok(())
     }
+
+    /// Sampling reads `/proc/stat` which only exists on linux.
+    #[cfg(not(target_os = "linux"))]
+    #[allow(clippy::unused_async)]
+    async fn sample(
+        _registry: &Registry,
+        _options: &Options,
+        _duration: Duration,
+    ) -> Result<(), Error> {
+        anyhow::bail!("sampling cpu utilization is only supported on linux")
+    }
+}
+
+impl CpuStat {
+    fn parse_all(s: &str) -> Result<Vec<Self>, Error> {
+        s.lines()
+            .filter(|line| line.starts_with("cpu"))
+            .map(|line| Self::from_str(line).map_err(Error::msg))
+            .collect()
+    }
+
+    /// Exports `system_cpu_seconds_total` and the time spent running guests
+    /// as `system_cpu_guest_seconds_total`. Guest time is already accounted
+    /// in user and nice so it would be counted twice as a mode of its own.
+    #[allow(clippy::cast_precision_loss)]
+    fn register(
+        registry: &Registry,
+        options: &Options,
+        stats: &[Self],
+        user_hz: f64,
+    ) -> Result<(), Error> {
+        let seconds = register_counter_vec_with_registry!(
+            "system_cpu_seconds_total",
+            "seconds the cpus spent in each mode",
+            &["cpu", "mode"],
+            registry
+        )?;
+
+        let guest = register_counter_vec_with_registry!(
+            "system_cpu_guest_seconds_total",
+            "seconds the cpus spent running guests, included in user and nice",
+            &["cpu", "mode"],
+            registry
+        )?;
+
+        for stat in stats {
+            let cpu = match stat.cpu {
+                None if options.total_cpu => "total".to_string(),
+                Some(index) if options.per_cpu => index.to_string(),
+                _ => continue,
+            };
+
+            for (mode, ticks) in stat.modes() {
+                let counter = match mode {
+                    "guest" => guest.with_label_values(&[&cpu, "user"]),
+                    "guest_nice" => guest.with_label_values(&[&cpu, "nice"]),
+                    _ => seconds.with_label_values(&[&cpu, mode]),
+                };
+
+                counter.inc_by(ticks as f64 / user_hz);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn modes(&self) -> [(&'static str, u64); 10] {
+        [
+            ("user", self.user),
+            ("nice", self.nice),
+            ("system", self.system),
+            ("idle", self.idle),
+            ("iowait", self.iowait),
+            ("irq", self.irq),
+            ("softirq", self.softirq),
+            ("steal", self.steal),
+            ("guest", self.guest),
+            ("guest_nice", self.guest_nice),
+        ]
+    }
+
+    /// Time spent in every mode since `previous` in percent. Guest time is
+    /// already accounted in user and nice so it is left out of the total.
+    #[allow(clippy::cast_precision_loss)]
+    fn utilization(&self, previous: &Self) -> Vec<(&'static str, f64)> {
+        let deltas = self
+            .modes()
+            .into_iter()
+            .zip(previous.modes())
+            .map(|((mode, now), (_, before))| (mode, now.saturating_sub(before)))
+            .collect::<Vec<_>>();
+
+        let total: u64 = deltas
+            .iter()
+            .filter(|(mode, _)| !matches!(*mode, "guest" | "guest_nice"))
+            .map(|(_, delta)| delta)
+            .sum();
+
+        deltas
+            .into_iter()
+            .map(|(mode, delta)| {
+                let percent = if total == 0 {
+                    0.0
+                } else {
+                    delta as f64 / total as f64 * 100.0
+                };
+
+                (mode, percent)
+            })
+            .collect()
+    }
+}
+
+impl Counters {
+    fn register(registry: &Registry) -> Result<Self, Error> {
+        Ok(Self {
+            user: register_int_counter_vec_with_registry!(
+                "system_cpu_user",
+                "system cpu user usage",
+                &["cpu"],
+                registry
+            )?,
+
+            nice: register_int_counter_vec_with_registry!(
+                "system_cpu_nice",
+                "system cpu nice usage",
+                &["cpu"],
+                registry
+            )?,
+
+            system: register_int_counter_vec_with_registry!(
+                "system_cpu_system",
+                "system cpu system usage",
+                &["cpu"],
+                registry
+            )?,
+
+            irq: register_int_counter_vec_with_registry!(
+                "system_cpu_irq",
+                "system cpu irq usage",
+                &["cpu"],
+                registry
+            )?,
+
+            idle: register_int_counter_vec_with_registry!(
+                "system_cpu_idle",
+                "system cpu idle usage",
+                &["cpu"],
+                registry
+            )?,
+
+            other: register_int_counter_vec_with_registry!(
+                "system_cpu_other",
+                "system cpu other usage",
+                &["cpu"],
+                registry
+            )?,
+        })
+    }
+
+    fn inc(&self, cpu: &str, time: &CpuTime) -> Result<(), Error> {
+        self.user
+            .with_label_values(&[cpu])
+            .inc_by(time.user.try_into()?);
+
+        self.nice
+            .with_label_values(&[cpu])
+            .inc_by(time.nice.try_into()?);
+
+        self.system
+            .with_label_values(&[cpu])
+            .inc_by(time.system.try_into()?);
+
+        self.irq
+            .with_label_values(&[cpu])
+            .inc_by(time.interrupt.try_into()?);
+
+        self.idle
+            .with_label_values(&[cpu])
+            .inc_by(time.idle.try_into()?);
+
+        self.other
+            .with_label_values(&[cpu])
+            .inc_by(time.other.try_into()?);
+
+        Ok(())
+    }
+}
+
+impl FromStr for CpuStat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.split_ascii_whitespace();
+
+        let cpu = match split.next() {
+            Some("cpu") => None,
+            Some(name) => Some(
+                name.strip_prefix("cpu")
+                    .and_then(|index| index.parse().ok())
+                    .ok_or_else(|| format!("invalid cpu name {name:?}"))?,
+            ),
+            None => return Err("empty cpu line".to_string()),
+        };
+
+        let values = split
+            .map(str::parse)
+            .collect::<Result<Vec<u64>, _>>()
+            .map_err(|err| format!("invalid value in cpu line {s:?}: {err}"))?;
+
+        // Older kernels have less columns, missing ones are left at zero.
+        if values.len() < 4 {
+            return Err(format!("not enough values in cpu line {s:?}"));
+        }
+
+        let value = |index: usize| values.get(index).copied().unwrap_or_default();
+
+        Ok(Self {
+            cpu,
+            user: value(0),
+            nice: value(1),
+            system: value(2),
+            idle: value(3),
+            iowait: value(4),
+            irq: value(5),
+            softirq: value(6),
+            steal: value(7),
+            guest: value(8),
+            guest_nice: value(9),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod parse {
+        use pretty_assertions::assert_eq;
+
+        use crate::probe::system::cpu::CpuStat;
+
+        #[test]
+        fn proc_stat() {
+            // head -n 6 /proc/stat
+            const INPUT: &str = r"cpu  2255 34 2290 22625563 6290 127 456 0 0 0
+cpu0 1132 34 1441 11311718 3675 127 438 0 0 0
+cpu1 1123 0 849 11313845 2614 0 18 0 0 0
+intr 114930548 113199788 3 0 5 263 0 4 [... lots more numbers ...]
+ctxt 1990473
+btime 1062191376";
+
+            let expected = vec![
+                CpuStat {
+                    cpu: None,
+                    user: 2255,
+                    nice: 34,
+                    system: 2290,
+                    idle: 22_625_563,
+                    iowait: 6290,
+                    irq: 127,
+                    softirq: 456,
+                    steal: 0,
+                    guest: 0,
+                    guest_nice: 0,
+                },
+                CpuStat {
+                    cpu: Some(0),
+                    user: 1132,
+                    nice: 34,
+                    system: 1441,
+                    idle: 11_311_718,
+                    iowait: 3675,
+                    irq: 127,
+                    softirq: 438,
+                    steal: 0,
+                    guest: 0,
+                    guest_nice: 0,
+                },
+                CpuStat {
+                    cpu: Some(1),
+                    user: 1123,
+                    nice: 0,
+                    system: 849,
+                    idle: 11_313_845,
+                    iowait: 2614,
+                    irq: 0,
+                    softirq: 18,
+                    steal: 0,
+                    guest: 0,
+                    guest_nice: 0,
+                },
+            ];
+
+            let got = CpuStat::parse_all(INPUT).unwrap();
+
+            assert_eq!(expected, got);
+        }
+
+        #[test]
+        fn steal_and_guest() {
+            const INPUT: &str = "cpu3 84721 120 20011 9315511 1742 0 1204 5523 312 7";
+
+            let expected = CpuStat {
+                cpu: Some(3),
+                user: 84721,
+                nice: 120,
+                system: 20011,
+                idle: 9_315_511,
+                iowait: 1742,
+                irq: 0,
+                softirq: 1204,
+                steal: 5523,
+                guest: 312,
+                guest_nice: 7,
+            };
+
+            let got = INPUT.parse::<CpuStat>().unwrap();
+
+            assert_eq!(expected, got);
+        }
+
+        #[test]
+        fn old_kernel() {
+            // linux 2.4 only has user, nice, system and idle
+            const INPUT: &str = "cpu  2255 34 2290 22625563";
+
+            let expected = CpuStat {
+                cpu: None,
+                user: 2255,
+                nice: 34,
+                system: 2290,
+                idle: 22_625_563,
+                ..CpuStat::default()
+            };
+
+            let got = INPUT.parse::<CpuStat>().unwrap();
+
+            assert_eq!(expected, got);
+        }
+
+        #[test]
+        fn utilization() {
+            let previous = "cpu0 100 0 100 700 50 0 50 0 0 0"
+                .parse::<CpuStat>()
+                .unwrap();
+            let current = "cpu0 300 0 200 1200 100 0 100 100 20 0"
+                .parse::<CpuStat>()
+                .unwrap();
+
+            let expected = vec![
+                ("user", 20.0),
+                ("nice", 0.0),
+                ("system", 10.0),
+                ("idle", 50.0),
+                ("iowait", 5.0),
+                ("irq", 0.0),
+                ("softirq", 5.0),
+                ("steal", 10.0),
+                ("guest", 2.0),
+                ("guest_nice", 0.0),
+            ];
+
+            let got = current.utilization(&previous);
+
+            assert_eq!(expected, got);
+        }
+
+        #[test]
+        fn invalid() {
+            assert!("cpux 1 2 3 4".parse::<CpuStat>().is_err());
+            assert!("cpu 1 2".parse::<CpuStat>().is_err());
+            assert!("cpu 1 2 a 4".parse::<CpuStat>().is_err());
+        }
+    }
+
+    mod register {
+        use pretty_assertions::assert_eq;
+        use prometheus::Registry;
+
+        use crate::probe::system::cpu::{
+            CpuStat,
+            Options,
+        };
+
+        /// Name, `cpu` and `mode` label and value of every exported counter.
+        fn counters(registry: &Registry) -> Vec<(String, String, String, f64)> {
+            registry
+                .gather()
+                .iter()
+                .flat_map(|family| {
+                    family.get_metric().iter().map(|metric| {
+                        (
+                            family.get_name().to_string(),
+                            metric.get_label()[0].get_value().to_string(),
+                            metric.get_label()[1].get_value().to_string(),
+                            metric.get_counter().get_value(),
+                        )
+                    })
+                })
+                .collect()
+        }
+
+        #[test]
+        fn guest() {
+            const INPUT: &str = "cpu0 300 40 200 1200 100 10 100 50 20 4";
+
+            let options = Options {
+                per_cpu: true,
+                total_cpu: false,
+                legacy_counters: false,
+            };
+
+            let expected = [
+                ("system_cpu_guest_seconds_total", "nice", 0.04),
+                ("system_cpu_guest_seconds_total", "user", 0.2),
+                ("system_cpu_seconds_total", "idle", 12.0),
+                ("system_cpu_seconds_total", "iowait", 1.0),
+                ("system_cpu_seconds_total", "irq", 0.1),
+                ("system_cpu_seconds_total", "nice", 0.4),
+                ("system_cpu_seconds_total", "softirq", 1.0),
+                ("system_cpu_seconds_total", "steal", 0.5),
+                ("system_cpu_seconds_total", "system", 2.0),
+                ("system_cpu_seconds_total", "user", 3.0),
+            ]
+            .map(|(name, mode, value)| (name.to_string(), "0".to_string(), mode.to_string(), value))
+            .to_vec();
+
+            let registry = Registry::new();
+            CpuStat::register(
+                &registry,
+                &options,
+                &CpuStat::parse_all(INPUT).unwrap(),
+                100.0,
+            )
+            .unwrap();
+
+            assert_eq!(expected, counters(&registry));
+        }
+
+        #[test]
+        fn per_cpu_and_total() {
+            const INPUT: &str = "cpu  400 0 200 1000 0 0 0 0 0 0
+cpu0 100 0 50 500 0 0 0 0 0 0
+cpu1 300 0 150 500 0 0 0 0 0 0";
+
+            let cases = [
+                (false, false, vec![]),
+                (true, false, vec![("0", 1.0), ("1", 3.0)]),
+                (false, true, vec![("total", 4.0)]),
+                (true, true, vec![("0", 1.0), ("1", 3.0), ("total", 4.0)]),
+            ];
+
+            for (per_cpu, total_cpu, expected) in cases {
+                let options = Options {
+                    per_cpu,
+                    total_cpu,
+                    legacy_counters: false,
+                };
+
+                let registry = Registry::new();
+                CpuStat::register(
+                    &registry,
+                    &options,
+                    &CpuStat::parse_all(INPUT).unwrap(),
+                    100.0,
+                )
+                .unwrap();
+
+                let expected = expected
+                    .into_iter()
+                    .map(|(cpu, value)| {
+                        (
+                            "system_cpu_seconds_total".to_string(),
+                            cpu.to_string(),
+                            "user".to_string(),
+                            value,
+                        )
+                    })
+                    .collect::<Vec<_>>();
+
+                let got = counters(&registry)
+                    .into_iter()
+                    .filter(|(name, _, mode, _)| {
+                        name == "system_cpu_seconds_total" && mode == "user"
+                    })
+                    .collect::<Vec<_>>();
+
+                assert_eq!(expected, got, "per_cpu: {per_cpu}, total_cpu: {total_cpu}");
+            }
+        }
+    }
 }
@@ -0,0 +1,373 @@
+use std::str::FromStr;
+
+use anyhow::Error;
+use prometheus::{
+    register_counter_vec_with_registry,
+    register_int_counter_vec_with_registry,
+    register_int_gauge_vec_with_registry,
+    Registry,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::probe::{
+    filter::Filter,
+    Probe,
+};
+
+/// `/proc/diskstats` always counts in 512 byte sectors independent of the
+/// sector size of the device.
+const SECTOR_SIZE: u64 = 512;
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct Params {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Options {
+    /// Filter on the device name like `sda` or `nvme0n1`. Loop and ram
+    /// devices are excluded by default.
+    pub(crate) devices: Filter,
+}
+
+#[derive(Debug)]
+pub(crate) struct Disk {}
+
+/// One line of `/proc/diskstats`. Times are in milliseconds.
+#[derive(Debug, Default, PartialEq)]
+struct DiskStat {
+    device: String,
+    reads_completed: u64,
+    reads_merged: u64,
+    sectors_read: u64,
+    read_time: u64,
+    writes_completed: u64,
+    writes_merged: u64,
+    sectors_written: u64,
+    write_time: u64,
+    io_in_progress: u64,
+    io_time: u64,
+    weighted_io_time: u64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            devices: Filter::exclude([r"(z?ram|loop|fd)\d+"]),
+        }
+    }
+}
+
+impl Probe for Disk {
+    const NAME: &'static str = "disk";
+    const LABELS: &'static [&'static str] = &["device"];
+
+    type Options = Options;
+    type Params = Params;
+
+    async fn collect(registry: &Registry, options: &Options, _params: Params) -> Result<(), Error> {
+        Self::run(registry, options)
+    }
+}
+
+impl Disk {
+    #[cfg(target_os = "linux")]
+    #[allow(clippy::cast_precision_loss, clippy::too_many_lines)]
+    fn run(registry: &Registry, options: &Options) -> Result<(), Error> {
+        let stats = DiskStat::parse_all(&std::fs::read_to_string("/proc/diskstats")?)?;
+
+        let reads_completed = register_int_counter_vec_with_registry!(
+            "system_disk_reads_completed_total",
+            "reads completed successfully",
+            &["device"],
+            registry
+        )?;
+
+        let reads_merged = register_int_counter_vec_with_registry!(
+            "system_disk_reads_merged_total",
+            "adjacent reads merged into one",
+            &["device"],
+            registry
+        )?;
+
+        let read_bytes = register_int_counter_vec_with_registry!(
+            "system_disk_read_bytes_total",
+            "bytes read",
+            &["device"],
+            registry
+        )?;
+
+        let read_time = register_counter_vec_with_registry!(
+            "system_disk_read_time_seconds_total",
+            "time spent reading",
+            &["device"],
+            registry
+        )?;
+
+        let writes_completed = register_int_counter_vec_with_registry!(
+            "system_disk_writes_completed_total",
+            "writes completed successfully",
+            &["device"],
+            registry
+        )?;
+
+        let writes_merged = register_int_counter_vec_with_registry!(
+            "system_disk_writes_merged_total",
+            "adjacent writes merged into one",
+            &["device"],
+            registry
+        )?;
+
+        let written_bytes = register_int_counter_vec_with_registry!(
+            "system_disk_written_bytes_total",
+            "bytes written",
+            &["device"],
+            registry
+        )?;
+
+        let write_time = register_counter_vec_with_registry!(
+            "system_disk_write_time_seconds_total",
+            "time spent writing",
+            &["device"],
+            registry
+        )?;
+
+        let io_in_progress = register_int_gauge_vec_with_registry!(
+            "system_disk_io_now",
+            "i/o operations currently in flight",
+            &["device"],
+            registry
+        )?;
+
+        let io_time = register_counter_vec_with_registry!(
+            "system_disk_io_time_seconds_total",
+            "time spent doing i/o operations",
+            &["device"],
+            registry
+        )?;
+
+        let weighted_io_time = register_counter_vec_with_registry!(
+            "system_disk_io_time_weighted_seconds_total",
+            "time spent doing i/o operations weighted by the number of operations in flight",
+            &["device"],
+            registry
+        )?;
+
+        for stat in stats {
+            if !options.devices.is_match(&stat.device) {
+                continue;
+            }
+
+            let device = &[stat.device.as_str()];
+
+            reads_completed
+                .with_label_values(device)
+                .inc_by(stat.reads_completed);
+
+            reads_merged
+                .with_label_values(device)
+                .inc_by(stat.reads_merged);
+
+            read_bytes
+                .with_label_values(device)
+                .inc_by(stat.sectors_read * SECTOR_SIZE);
+
+            read_time
+                .with_label_values(device)
+                .inc_by(stat.read_time as f64 / 1000.0);
+
+            writes_completed
+                .with_label_values(device)
+                .inc_by(stat.writes_completed);
+
+            writes_merged
+                .with_label_values(device)
+                .inc_by(stat.writes_merged);
+
+            written_bytes
+                .with_label_values(device)
+                .inc_by(stat.sectors_written * SECTOR_SIZE);
+
+            write_time
+                .with_label_values(device)
+                .inc_by(stat.write_time as f64 / 1000.0);
+
+            io_in_progress
+                .with_label_values(device)
+                .set(stat.io_in_progress.try_into()?);
+
+            io_time
+                .with_label_values(device)
+                .inc_by(stat.io_time as f64 / 1000.0);
+
+            weighted_io_time
+                .with_label_values(device)
+                .inc_by(stat.weighted_io_time as f64 / 1000.0);
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn run(_registry: &Registry, _options: &Options) -> Result<(), Error> {
+        anyhow::bail!("disk statistics are only supported on linux")
+    }
+}
+
+impl DiskStat {
+    fn parse_all(s: &str) -> Result<Vec<Self>, Error> {
+        s.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Self::from_str(line).map_err(Error::msg))
+            .collect()
+    }
+}
+
+impl FromStr for DiskStat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split = s.split_ascii_whitespace().collect::<Vec<_>>();
+
+        // Newer kernels append discard and flush statistics which are ignored.
+        let [_major, _minor, device, values @ ..] = split.as_slice() else {
+            return Err(format!("missing device in diskstats line {s:?}"));
+        };
+
+        if values.len() < 11 {
+            return Err(format!("not enough values in diskstats line {s:?}"));
+        }
+
+        let values = values[..11]
+            .iter()
+            .map(|value| value.parse())
+            .collect::<Result<Vec<u64>, _>>()
+            .map_err(|err| format!("invalid value in diskstats line {s:?}: {err}"))?;
+
+        Ok(Self {
+            device: (*device).to_string(),
+            reads_completed: values[0],
+            reads_merged: values[1],
+            sectors_read: values[2],
+            read_time: values[3],
+            writes_completed: values[4],
+            writes_merged: values[5],
+            sectors_written: values[6],
+            write_time: values[7],
+            io_in_progress: values[8],
+            io_time: values[9],
+            weighted_io_time: values[10],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod parse {
+        use pretty_assertions::assert_eq;
+
+        use crate::probe::system::disk::DiskStat;
+
+        #[test]
+        fn linux_4_4() {
+            // cat /proc/diskstats
+            const INPUT: &str = r"
+   8       0 sda 126204 3384 8467530 97416 1034593 1205262 30574824 1563692 0 327444 1660880
+   8       1 sda1 125943 3384 8465434 97376 1002711 1205262 30574824 1557516 0 321824 1654704
+   7       0 loop0 0 0 0 0 0 0 0 0 0 0 0";
+
+            let expected = vec![
+                DiskStat {
+                    device: "sda".to_string(),
+                    reads_completed: 126_204,
+                    reads_merged: 3384,
+                    sectors_read: 8_467_530,
+                    read_time: 97416,
+                    writes_completed: 1_034_593,
+                    writes_merged: 1_205_262,
+                    sectors_written: 30_574_824,
+                    write_time: 1_563_692,
+                    io_in_progress: 0,
+                    io_time: 327_444,
+                    weighted_io_time: 1_660_880,
+                },
+                DiskStat {
+                    device: "sda1".to_string(),
+                    reads_completed: 125_943,
+                    reads_merged: 3384,
+                    sectors_read: 8_465_434,
+                    read_time: 97376,
+                    writes_completed: 1_002_711,
+                    writes_merged: 1_205_262,
+                    sectors_written: 30_574_824,
+                    write_time: 1_557_516,
+                    io_in_progress: 0,
+                    io_time: 321_824,
+                    weighted_io_time: 1_654_704,
+                },
+                DiskStat {
+                    device: "loop0".to_string(),
+                    ..DiskStat::default()
+                },
+            ];
+
+            let got = DiskStat::parse_all(INPUT).unwrap();
+
+            assert_eq!(expected, got);
+        }
+
+        #[test]
+        fn linux_5_10() {
+            // cat /proc/diskstats
+            const INPUT: &str = r"
+ 259       0 nvme0n1 3245371 1043522 303146918 1226862 6148402 3938183 469380344 11484737 2 2785904 12942398 0 0 0 0 187354 230798
+ 254       0 vda 7435 5382 3029122 14757 5433 18640 4272576 17979 0 6024 33311 3117 0 684640 572 72 1";
+
+            let expected = vec![
+                DiskStat {
+                    device: "nvme0n1".to_string(),
+                    reads_completed: 3_245_371,
+                    reads_merged: 1_043_522,
+                    sectors_read: 303_146_918,
+                    read_time: 1_226_862,
+                    writes_completed: 6_148_402,
+                    writes_merged: 3_938_183,
+                    sectors_written: 469_380_344,
+                    write_time: 11_484_737,
+                    io_in_progress: 2,
+                    io_time: 2_785_904,
+                    weighted_io_time: 12_942_398,
+                },
+                DiskStat {
+                    device: "vda".to_string(),
+                    reads_completed: 7435,
+                    reads_merged: 5382,
+                    sectors_read: 3_029_122,
+                    read_time: 14757,
+                    writes_completed: 5433,
+                    writes_merged: 18640,
+                    sectors_written: 4_272_576,
+                    write_time: 17979,
+                    io_in_progress: 0,
+                    io_time: 6024,
+                    weighted_io_time: 33311,
+                },
+            ];
+
+            let got = DiskStat::parse_all(INPUT).unwrap();
+
+            assert_eq!(expected, got);
+        }
+
+        #[test]
+        fn invalid() {
+            assert!("   8       0".parse::<DiskStat>().is_err());
+            assert!("   8       0 sda 1 2 3".parse::<DiskStat>().is_err());
+            assert!("   8       0 sda 1 2 3 4 5 6 7 8 9 10 x"
+                .parse::<DiskStat>()
+                .is_err());
+        }
+    }
+}
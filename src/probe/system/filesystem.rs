@@ -0,0 +1,268 @@
+use anyhow::Error;
+use prometheus::{
+    register_int_gauge_vec_with_registry,
+    Registry,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use systemstat::{
+    Platform,
+    System,
+};
+
+use crate::probe::{
+    filter::Filter,
+    Probe,
+};
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct Params {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Options {
+    /// Filter on the filesystem type like `ext4` or `tmpfs`. Pseudo
+    /// filesystems are excluded by default.
+    pub(crate) fs_types: Filter,
+
+    /// Filter on the path the filesystem is mounted on.
+    pub(crate) mountpoints: Filter,
+}
+
+#[derive(Debug)]
+pub(crate) struct Filesystem {}
+
+/// Values of a mounted filesystem that passed the filters.
+#[derive(Debug, PartialEq)]
+struct Mount {
+    mountpoint: String,
+    device: String,
+    fs_type: String,
+    size: i64,
+    free: i64,
+    available: i64,
+    inodes: i64,
+    inodes_free: i64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            fs_types: Filter::exclude([
+                "autofs",
+                "binfmt_misc",
+                "bpf",
+                "cgroup2?",
+                "configfs",
+                "debugfs",
+                "devfs",
+                "devpts",
+                "devtmpfs",
+                "fdescfs",
+                "fusectl",
+                "hugetlbfs",
+                "linprocfs",
+                "mqueue",
+                "nsfs",
+                "overlay",
+                "proc",
+                "procfs",
+                "pstore",
+                "rpc_pipefs",
+                "securityfs",
+                "selinuxfs",
+                "squashfs",
+                "sysfs",
+                "tmpfs",
+                "tracefs",
+            ]),
+            mountpoints: Filter::exclude(["/(dev|proc|run|sys)(/.*)?", "/var/lib/docker/.+"]),
+        }
+    }
+}
+
+impl Probe for Filesystem {
+    const NAME: &'static str = "filesystem";
+    const LABELS: &'static [&'static str] = &["mountpoint", "device", "fstype"];
+
+    type Options = Options;
+    type Params = Params;
+
+    async fn collect(registry: &Registry, options: &Options, _params: Params) -> Result<(), Error> {
+        Self::run(registry, options)
+    }
+}
+
+impl Filesystem {
+    fn run(registry: &Registry, options: &Options) -> Result<(), Error> {
+        let sys = System::new();
+        let mounts = Mount::parse_all(sys.mounts()?, options)?;
+
+        let labels = &["mountpoint", "device", "fstype"];
+
+        let size = register_int_gauge_vec_with_registry!(
+            "system_filesystem_size_byte",
+            "total size of the filesystem",
+            labels,
+            registry
+        )?;
+
+        let free = register_int_gauge_vec_with_registry!(
+            "system_filesystem_free_byte",
+            "free space on the filesystem",
+            labels,
+            registry
+        )?;
+
+        let available = register_int_gauge_vec_with_registry!(
+            "system_filesystem_available_byte",
+            "free space on the filesystem available to non root users",
+            labels,
+            registry
+        )?;
+
+        let inodes = register_int_gauge_vec_with_registry!(
+            "system_filesystem_inodes_count",
+            "total inodes of the filesystem",
+            labels,
+            registry
+        )?;
+
+        let inodes_free = register_int_gauge_vec_with_registry!(
+            "system_filesystem_inodes_free_count",
+            "free inodes of the filesystem",
+            labels,
+            registry
+        )?;
+
+        for mount in mounts {
+            let values = &[
+                mount.mountpoint.as_str(),
+                mount.device.as_str(),
+                mount.fs_type.as_str(),
+            ];
+
+            size.with_label_values(values).set(mount.size);
+            free.with_label_values(values).set(mount.free);
+            available.with_label_values(values).set(mount.available);
+            inodes.with_label_values(values).set(mount.inodes);
+            inodes_free.with_label_values(values).set(mount.inodes_free);
+        }
+
+        Ok(())
+    }
+}
+
+impl Mount {
+    /// Converts the filesystems reported by the os and drops the ones the
+    /// filters of `options` exclude.
+    fn parse_all(
+        filesystems: Vec<systemstat::Filesystem>,
+        options: &Options,
+    ) -> Result<Vec<Self>, Error> {
+        filesystems
+            .into_iter()
+            .filter(|filesystem| {
+                options.fs_types.is_match(&filesystem.fs_type)
+                    && options.mountpoints.is_match(&filesystem.fs_mounted_on)
+            })
+            .map(|filesystem| {
+                Ok(Self {
+                    size: filesystem.total.as_u64().try_into()?,
+                    free: filesystem.free.as_u64().try_into()?,
+                    available: filesystem.avail.as_u64().try_into()?,
+                    inodes: filesystem.files_total.try_into()?,
+                    inodes_free: filesystem
+                        .files_total
+                        .saturating_sub(filesystem.files)
+                        .try_into()?,
+                    mountpoint: filesystem.fs_mounted_on,
+                    device: filesystem.fs_mounted_from,
+                    fs_type: filesystem.fs_type,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod parse {
+        use pretty_assertions::assert_eq;
+        use systemstat::ByteSize;
+
+        use crate::probe::system::filesystem::{
+            Mount,
+            Options,
+        };
+
+        fn filesystem(fs_type: &str, device: &str, mountpoint: &str) -> systemstat::Filesystem {
+            systemstat::Filesystem {
+                files: 1_000,
+                files_total: 65_536,
+                files_avail: 64_536,
+                free: ByteSize::b(40_000_000_000),
+                avail: ByteSize::b(35_000_000_000),
+                total: ByteSize::b(100_000_000_000),
+                name_max: 255,
+                fs_type: fs_type.to_string(),
+                fs_mounted_from: device.to_string(),
+                fs_mounted_on: mountpoint.to_string(),
+            }
+        }
+
+        #[test]
+        fn default_filter() {
+            let tests = [
+                ("ext4", "/dev/sda1", "/", true),
+                ("ext4", "/dev/sda2", "/home", true),
+                ("xfs", "/dev/sdb1", "/var/lib/docker", true),
+                ("tmpfs", "tmpfs", "/tmp", false),
+                (
+                    "overlay",
+                    "overlay",
+                    "/var/lib/docker/overlay2/x/merged",
+                    false,
+                ),
+                ("ext4", "/dev/sdb1", "/var/lib/docker/overlay2/x", false),
+                ("proc", "proc", "/proc", false),
+                ("cgroup2", "cgroup2", "/sys/fs/cgroup", false),
+                ("ext4", "/dev/sda3", "/run/media/usb", false),
+            ];
+
+            for (fs_type, device, mountpoint, expected) in tests {
+                let mounts = Mount::parse_all(
+                    vec![filesystem(fs_type, device, mountpoint)],
+                    &Options::default(),
+                )
+                .unwrap();
+
+                assert_eq!(expected, !mounts.is_empty(), "{fs_type} on {mountpoint}");
+            }
+        }
+
+        #[test]
+        fn values() {
+            let got = Mount::parse_all(
+                vec![filesystem("ext4", "/dev/sda1", "/")],
+                &Options::default(),
+            )
+            .unwrap();
+
+            let expected = vec![Mount {
+                mountpoint: "/".to_string(),
+                device: "/dev/sda1".to_string(),
+                fs_type: "ext4".to_string(),
+                size: 100_000_000_000,
+                free: 40_000_000_000,
+                available: 35_000_000_000,
+                inodes: 65_536,
+                inodes_free: 64_536,
+            }];
+
+            assert_eq!(expected, got);
+        }
+    }
+}
@@ -1,40 +1,48 @@
 use anyhow::Error;
-use axum::extract::Query;
 use prometheus::{
     register_int_gauge_vec_with_registry,
     register_int_gauge_with_registry,
-    Encoder,
     Registry,
-    TextEncoder,
 };
-use serde::Deserialize;
+use serde::{
+    Deserialize,
+    Serialize,
+};
 use systemstat::{
     Platform,
     System,
 };
 
-#[derive(Debug, Deserialize)]
+use crate::probe::Probe;
+
+#[derive(Debug, Default, Deserialize)]
 pub(crate) struct Params {}
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct Options {}
+
 #[derive(Debug)]
-pub(super) struct Memory {}
+pub(crate) struct Memory {}
 
-#[allow(clippy::unused_async)]
-pub(crate) async fn handler(Query(_params): Query<Params>) -> Vec<u8> {
-    let registry = Registry::new();
-    Memory::run(&registry).unwrap();
+impl Probe for Memory {
+    const NAME: &'static str = "memory";
+    const LABELS: &'static [&'static str] = &["os", "name"];
 
-    let mut buffer = vec![];
-    let encoder = TextEncoder::new();
-    let metric_families = registry.gather();
-    encoder.encode(&metric_families, &mut buffer).unwrap();
+    type Options = Options;
+    type Params = Params;
 
-    buffer
+    async fn collect(
+        registry: &Registry,
+        _options: &Options,
+        _params: Params,
+    ) -> Result<(), Error> {
+        Self::run(registry)
+    }
 }
 
 impl Memory {
     #[allow(clippy::cast_possible_wrap)]
-    pub(super) fn run(registry: &Registry) -> Result<(), Error> {
+    fn run(registry: &Registry) -> Result<(), Error> {
         let sys = System::new();
 
         let memory = sys.memory()?;
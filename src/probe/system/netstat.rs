@@ -0,0 +1,405 @@
+use std::collections::BTreeMap;
+
+use anyhow::Error;
+use prometheus::{
+    register_counter_vec_with_registry,
+    register_gauge_vec_with_registry,
+    register_int_gauge_vec_with_registry,
+    Registry,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::probe::{
+    filter::Filter,
+    Probe,
+};
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct Params {}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Options {
+    /// Filter on the kernel statistics as `<protocol>_<name>` like
+    /// `TcpExt_ListenOverflows` or `Udp_RcvbufErrors`.
+    pub(crate) statistics: Filter,
+}
+
+#[derive(Debug)]
+pub(crate) struct Netstat {}
+
+/// A single value from `/proc/net/snmp`, `/proc/net/netstat` or
+/// `/proc/net/sockstat`.
+#[derive(Debug, PartialEq)]
+struct Statistic {
+    protocol: String,
+    name: String,
+    value: f64,
+}
+
+impl Probe for Netstat {
+    const NAME: &'static str = "netstat";
+    const LABELS: &'static [&'static str] = &["protocol", "state", "name"];
+
+    type Options = Options;
+    type Params = Params;
+
+    async fn collect(registry: &Registry, options: &Options, _params: Params) -> Result<(), Error> {
+        Self::run(registry, options)
+    }
+}
+
+impl Netstat {
+    #[cfg(target_os = "linux")]
+    fn run(registry: &Registry, options: &Options) -> Result<(), Error> {
+        let connections = register_int_gauge_vec_with_registry!(
+            "system_netstat_tcp_connections",
+            "tcp sockets per state",
+            &["protocol", "state"],
+            registry
+        )?;
+
+        for protocol in ["tcp", "tcp6"] {
+            // tcp6 is missing if ipv6 is disabled.
+            let Ok(content) = std::fs::read_to_string(format!("/proc/net/{protocol}")) else {
+                continue;
+            };
+
+            for (state, count) in parse_tcp_states(&content).map_err(Error::msg)? {
+                connections
+                    .with_label_values(&[protocol, state])
+                    .set(count.try_into()?);
+            }
+        }
+
+        let counters = register_counter_vec_with_registry!(
+            "system_netstat_total",
+            "protocol counters of the kernel",
+            &["protocol", "name"],
+            registry
+        )?;
+
+        let gauges = register_gauge_vec_with_registry!(
+            "system_netstat_value",
+            "protocol settings and current values of the kernel",
+            &["protocol", "name"],
+            registry
+        )?;
+
+        let snmp = parse_snmp(&std::fs::read_to_string("/proc/net/snmp")?).map_err(Error::msg)?;
+        let netstat =
+            parse_snmp(&std::fs::read_to_string("/proc/net/netstat")?).map_err(Error::msg)?;
+
+        for statistic in snmp.into_iter().chain(netstat) {
+            if !options.statistics.is_match(&statistic.key()) {
+                continue;
+            }
+
+            let labels = [statistic.protocol.as_str(), statistic.name.as_str()];
+
+            if statistic.is_gauge() {
+                gauges.with_label_values(&labels).set(statistic.value);
+            } else {
+                counters.with_label_values(&labels).inc_by(statistic.value);
+            }
+        }
+
+        let sockstat = register_gauge_vec_with_registry!(
+            "system_netstat_sockstat",
+            "socket usage of the kernel",
+            &["protocol", "name"],
+            registry
+        )?;
+
+        for statistic in
+            parse_sockstat(&std::fs::read_to_string("/proc/net/sockstat")?).map_err(Error::msg)?
+        {
+            if options.statistics.is_match(&statistic.key()) {
+                sockstat
+                    .with_label_values(&[&statistic.protocol, &statistic.name])
+                    .set(statistic.value);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn run(_registry: &Registry, _options: &Options) -> Result<(), Error> {
+        anyhow::bail!("netstat statistics are only supported on linux")
+    }
+}
+
+impl Statistic {
+    fn key(&self) -> String {
+        format!("{}_{}", self.protocol, self.name)
+    }
+
+    /// Whether the statistic of `/proc/net/snmp` or `/proc/net/netstat` is a
+    /// setting or a current value. Everything else counts events since boot.
+    fn is_gauge(&self) -> bool {
+        matches!(
+            (self.protocol.as_str(), self.name.as_str()),
+            ("Ip", "Forwarding" | "DefaultTTL")
+                | (
+                    "Tcp",
+                    "RtoAlgorithm" | "RtoMin" | "RtoMax" | "MaxConn" | "CurrEstab"
+                )
+        )
+    }
+}
+
+fn tcp_state(code: &str) -> Option<&'static str> {
+    let state = match code {
+        "01" => "established",
+        "02" => "syn_sent",
+        "03" => "syn_recv",
+        "04" => "fin_wait1",
+        "05" => "fin_wait2",
+        "06" => "time_wait",
+        "07" => "close",
+        "08" => "close_wait",
+        "09" => "last_ack",
+        "0A" => "listen",
+        "0B" => "closing",
+        "0C" => "new_syn_recv",
+        _ => return None,
+    };
+
+    Some(state)
+}
+
+/// Counts the sockets in `/proc/net/tcp` or `/proc/net/tcp6` per state.
+fn parse_tcp_states(s: &str) -> Result<BTreeMap<&'static str, u64>, String> {
+    let mut out = BTreeMap::new();
+
+    // The first line is the table header.
+    for line in s.lines().skip(1).filter(|line| !line.trim().is_empty()) {
+        let code = line
+            .split_ascii_whitespace()
+            .nth(3)
+            .ok_or_else(|| format!("missing state in tcp line {line:?}"))?;
+
+        let state = tcp_state(code).ok_or_else(|| format!("unknown tcp state {code:?}"))?;
+
+        *out.entry(state).or_default() += 1;
+    }
+
+    Ok(out)
+}
+
+/// Parses the format of `/proc/net/snmp` and `/proc/net/netstat` where every
+/// protocol has a line with the names followed by a line with the values.
+fn parse_snmp(s: &str) -> Result<Vec<Statistic>, String> {
+    let mut out = Vec::new();
+    let mut lines = s.lines().filter(|line| !line.trim().is_empty());
+
+    while let Some(names) = lines.next() {
+        let values = lines
+            .next()
+            .ok_or_else(|| format!("missing values for line {names:?}"))?;
+
+        let (protocol, names) = names
+            .split_once(':')
+            .ok_or_else(|| format!("missing protocol in line {names:?}"))?;
+
+        let (value_protocol, values) = values
+            .split_once(':')
+            .ok_or_else(|| format!("missing protocol in line {values:?}"))?;
+
+        if protocol != value_protocol {
+            return Err(format!(
+                "protocol of names {protocol:?} and values {value_protocol:?} do not match"
+            ));
+        }
+
+        let names = names.split_ascii_whitespace().collect::<Vec<_>>();
+        let values = values.split_ascii_whitespace().collect::<Vec<_>>();
+
+        if names.len() != values.len() {
+            return Err(format!(
+                "{protocol} has {} names but {} values",
+                names.len(),
+                values.len()
+            ));
+        }
+
+        for (name, value) in names.into_iter().zip(values) {
+            out.push(Statistic {
+                protocol: protocol.to_string(),
+                name: name.to_string(),
+                value: value
+                    .parse()
+                    .map_err(|err| format!("invalid value for {protocol} {name}: {err}"))?,
+            });
+        }
+    }
+
+    Ok(out)
+}
+
+/// Parses `/proc/net/sockstat` where every line is a protocol followed by
+/// pairs of names and values.
+fn parse_sockstat(s: &str) -> Result<Vec<Statistic>, String> {
+    let mut out = Vec::new();
+
+    for line in s.lines().filter(|line| !line.trim().is_empty()) {
+        let (protocol, pairs) = line
+            .split_once(':')
+            .ok_or_else(|| format!("missing protocol in sockstat line {line:?}"))?;
+
+        let pairs = pairs.split_ascii_whitespace().collect::<Vec<_>>();
+
+        for pair in pairs.chunks(2) {
+            let [name, value] = pair else {
+                return Err(format!("missing value in sockstat line {line:?}"));
+            };
+
+            out.push(Statistic {
+                protocol: protocol.to_string(),
+                name: (*name).to_string(),
+                value: value
+                    .parse()
+                    .map_err(|err| format!("invalid value for {protocol} {name}: {err}"))?,
+            });
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    mod parse {
+        use std::collections::BTreeMap;
+
+        use pretty_assertions::assert_eq;
+
+        use crate::probe::system::netstat::{
+            parse_snmp,
+            parse_sockstat,
+            parse_tcp_states,
+            Statistic,
+        };
+
+        fn statistic(protocol: &str, name: &str, value: f64) -> Statistic {
+            Statistic {
+                protocol: protocol.to_string(),
+                name: name.to_string(),
+                value,
+            }
+        }
+
+        #[test]
+        fn tcp_states() {
+            // cat /proc/net/tcp
+            const INPUT: &str = r"  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000:07E8 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 662 1 0000000069b73c0c 100 0 0 10 0
+   1: 0100007F:BC8F 00000000:0000 0A 00000000:00000000 00:00000000 00000000 65534        0 924 1 000000009b6c9382 100 0 0 10 0
+   2: 0100007F:17EA 0100007F:A2B4 01 00000000:00000000 00:00000000 00000000  1000        0 1337 1 0000000000000000 20 4 30 10 -1
+   3: 0100007F:A2B4 0100007F:17EA 06 00000000:00000000 03:00001770 00000000     0        0 0 3 0000000000000000";
+
+            let expected = BTreeMap::from([("established", 1), ("listen", 2), ("time_wait", 1)]);
+
+            let got = parse_tcp_states(INPUT).unwrap();
+
+            assert_eq!(expected, got);
+        }
+
+        #[test]
+        fn snmp() {
+            // head -n 4 /proc/net/snmp
+            const INPUT: &str = r"Tcp: RtoAlgorithm RtoMin RtoMax MaxConn ActiveOpens RetransSegs
+Tcp: 1 200 120000 -1 1532 17
+Udp: InDatagrams NoPorts InErrors RcvbufErrors
+Udp: 5421 12 3 3";
+
+            let expected = vec![
+                statistic("Tcp", "RtoAlgorithm", 1.0),
+                statistic("Tcp", "RtoMin", 200.0),
+                statistic("Tcp", "RtoMax", 120_000.0),
+                statistic("Tcp", "MaxConn", -1.0),
+                statistic("Tcp", "ActiveOpens", 1532.0),
+                statistic("Tcp", "RetransSegs", 17.0),
+                statistic("Udp", "InDatagrams", 5421.0),
+                statistic("Udp", "NoPorts", 12.0),
+                statistic("Udp", "InErrors", 3.0),
+                statistic("Udp", "RcvbufErrors", 3.0),
+            ];
+
+            let got = parse_snmp(INPUT).unwrap();
+
+            assert_eq!(expected, got);
+        }
+
+        #[test]
+        fn netstat() {
+            // cat /proc/net/netstat
+            const INPUT: &str = r"TcpExt: SyncookiesSent SyncookiesRecv ListenOverflows ListenDrops
+TcpExt: 4 2 130 131
+";
+
+            let expected = vec![
+                statistic("TcpExt", "SyncookiesSent", 4.0),
+                statistic("TcpExt", "SyncookiesRecv", 2.0),
+                statistic("TcpExt", "ListenOverflows", 130.0),
+                statistic("TcpExt", "ListenDrops", 131.0),
+            ];
+
+            let got = parse_snmp(INPUT).unwrap();
+
+            assert_eq!(expected, got);
+        }
+
+        #[test]
+        fn gauges() {
+            let gauges = [
+                statistic("Ip", "Forwarding", 1.0),
+                statistic("Ip", "DefaultTTL", 64.0),
+                statistic("Tcp", "MaxConn", -1.0),
+                statistic("Tcp", "CurrEstab", 4.0),
+                statistic("Ip", "InReceives", 5421.0),
+                statistic("Tcp", "ActiveOpens", 1532.0),
+                statistic("TcpExt", "ListenOverflows", 130.0),
+            ]
+            .iter()
+            .map(Statistic::is_gauge)
+            .collect::<Vec<_>>();
+
+            assert_eq!(vec![true, true, true, true, false, false, false], gauges);
+        }
+
+        #[test]
+        fn snmp_invalid() {
+            assert!(parse_snmp("Tcp: RtoAlgorithm RtoMin").is_err());
+            assert!(parse_snmp("Tcp: RtoAlgorithm RtoMin\nTcp: 1").is_err());
+            assert!(parse_snmp("Tcp: RtoAlgorithm\nUdp: 1").is_err());
+        }
+
+        #[test]
+        fn sockstat() {
+            // cat /proc/net/sockstat
+            const INPUT: &str = r"sockets: used 180
+TCP: inuse 4 orphan 0 tw 1 alloc 6 mem 1
+FRAG: inuse 0 memory 0";
+
+            let expected = vec![
+                statistic("sockets", "used", 180.0),
+                statistic("TCP", "inuse", 4.0),
+                statistic("TCP", "orphan", 0.0),
+                statistic("TCP", "tw", 1.0),
+                statistic("TCP", "alloc", 6.0),
+                statistic("TCP", "mem", 1.0),
+                statistic("FRAG", "inuse", 0.0),
+                statistic("FRAG", "memory", 0.0),
+            ];
+
+            let got = parse_sockstat(INPUT).unwrap();
+
+            assert_eq!(expected, got);
+        }
+    }
+}
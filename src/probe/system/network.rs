@@ -0,0 +1,378 @@
+use std::str::FromStr;
+
+use anyhow::Error;
+use prometheus::{
+    register_int_counter_vec_with_registry,
+    register_int_gauge_vec_with_registry,
+    IntCounterVec,
+    Registry,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::probe::{
+    filter::Filter,
+    Probe,
+};
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct Params {}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Options {
+    /// Filter on the interface name like `eth0` or `lo`.
+    pub(crate) interfaces: Filter,
+}
+
+#[derive(Debug)]
+pub(crate) struct Network {}
+
+/// One interface line of `/proc/net/dev`.
+#[derive(Debug, Default, PartialEq)]
+struct InterfaceStat {
+    interface: String,
+    receive_bytes: u64,
+    receive_packets: u64,
+    receive_errors: u64,
+    receive_drop: u64,
+    receive_fifo: u64,
+    receive_multicast: u64,
+    transmit_bytes: u64,
+    transmit_packets: u64,
+    transmit_errors: u64,
+    transmit_drop: u64,
+    transmit_fifo: u64,
+}
+
+impl Probe for Network {
+    const NAME: &'static str = "network";
+    const LABELS: &'static [&'static str] = &["interface"];
+
+    type Options = Options;
+    type Params = Params;
+
+    async fn collect(registry: &Registry, options: &Options, _params: Params) -> Result<(), Error> {
+        Self::run(registry, options)
+    }
+}
+
+impl Network {
+    #[cfg(target_os = "linux")]
+    fn run(registry: &Registry, options: &Options) -> Result<(), Error> {
+        let stats = InterfaceStat::parse_all(&std::fs::read_to_string("/proc/net/dev")?)?
+            .into_iter()
+            .filter(|stat| options.interfaces.is_match(&stat.interface))
+            .collect::<Vec<_>>();
+
+        Self::counters(registry, &stats)?;
+        Self::link(registry, &stats)?;
+
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn run(_registry: &Registry, _options: &Options) -> Result<(), Error> {
+        anyhow::bail!("network statistics are only supported on linux")
+    }
+
+    fn counters(registry: &Registry, stats: &[InterfaceStat]) -> Result<(), Error> {
+        let counter = |name: &str, help: &str| -> Result<IntCounterVec, Error> {
+            Ok(register_int_counter_vec_with_registry!(
+                name,
+                help,
+                &["interface"],
+                registry
+            )?)
+        };
+
+        let receive_bytes = counter("system_network_receive_bytes_total", "bytes received")?;
+        let receive_packets = counter("system_network_receive_packets_total", "packets received")?;
+        let receive_errors = counter(
+            "system_network_receive_errors_total",
+            "receive errors detected by the driver",
+        )?;
+        let receive_drop = counter(
+            "system_network_receive_drop_total",
+            "received packets dropped",
+        )?;
+        let receive_fifo = counter(
+            "system_network_receive_fifo_total",
+            "receive fifo buffer errors",
+        )?;
+        let receive_multicast = counter(
+            "system_network_receive_multicast_total",
+            "multicast frames received",
+        )?;
+        let transmit_bytes = counter("system_network_transmit_bytes_total", "bytes transmitted")?;
+        let transmit_packets = counter(
+            "system_network_transmit_packets_total",
+            "packets transmitted",
+        )?;
+        let transmit_errors = counter(
+            "system_network_transmit_errors_total",
+            "transmit errors detected by the driver",
+        )?;
+        let transmit_drop = counter(
+            "system_network_transmit_drop_total",
+            "transmitted packets dropped",
+        )?;
+        let transmit_fifo = counter(
+            "system_network_transmit_fifo_total",
+            "transmit fifo buffer errors",
+        )?;
+
+        for stat in stats {
+            let interface = &[stat.interface.as_str()];
+
+            receive_bytes
+                .with_label_values(interface)
+                .inc_by(stat.receive_bytes);
+            receive_packets
+                .with_label_values(interface)
+                .inc_by(stat.receive_packets);
+            receive_errors
+                .with_label_values(interface)
+                .inc_by(stat.receive_errors);
+            receive_drop
+                .with_label_values(interface)
+                .inc_by(stat.receive_drop);
+            receive_fifo
+                .with_label_values(interface)
+                .inc_by(stat.receive_fifo);
+            receive_multicast
+                .with_label_values(interface)
+                .inc_by(stat.receive_multicast);
+            transmit_bytes
+                .with_label_values(interface)
+                .inc_by(stat.transmit_bytes);
+            transmit_packets
+                .with_label_values(interface)
+                .inc_by(stat.transmit_packets);
+            transmit_errors
+                .with_label_values(interface)
+                .inc_by(stat.transmit_errors);
+            transmit_drop
+                .with_label_values(interface)
+                .inc_by(stat.transmit_drop);
+            transmit_fifo
+                .with_label_values(interface)
+                .inc_by(stat.transmit_fifo);
+        }
+
+        Ok(())
+    }
+
+    /// Link information from `/sys/class/net`. Attributes the driver does not
+    /// support (like the speed of virtual interfaces) are skipped.
+    fn link(registry: &Registry, stats: &[InterfaceStat]) -> Result<(), Error> {
+        let up = register_int_gauge_vec_with_registry!(
+            "system_network_up",
+            "if the interface is up or in an unknown state with a carrier",
+            &["interface"],
+            registry
+        )?;
+
+        let mtu = register_int_gauge_vec_with_registry!(
+            "system_network_mtu_byte",
+            "maximum transmission unit of the interface",
+            &["interface"],
+            registry
+        )?;
+
+        let speed = register_int_gauge_vec_with_registry!(
+            "system_network_speed_byte_per_second",
+            "negotiated link speed of the interface",
+            &["interface"],
+            registry
+        )?;
+
+        let carrier = register_int_gauge_vec_with_registry!(
+            "system_network_carrier",
+            "if the interface has a physical link",
+            &["interface"],
+            registry
+        )?;
+
+        for stat in stats {
+            let interface = stat.interface.as_str();
+
+            // Reading the carrier of an interface that is down fails.
+            let has_carrier =
+                sys_class_net(interface, "carrier").and_then(|v| v.parse::<i64>().ok());
+
+            if let Some(operstate) = sys_class_net(interface, "operstate") {
+                up.with_label_values(&[interface])
+                    .set(is_up(&operstate, has_carrier).into());
+            }
+
+            if let Some(value) = sys_class_net(interface, "mtu").and_then(|v| v.parse().ok()) {
+                mtu.with_label_values(&[interface]).set(value);
+            }
+
+            // The speed is in megabits per second and -1 if unknown.
+            if let Some(value) = sys_class_net(interface, "speed")
+                .and_then(|v| v.parse::<i64>().ok())
+                .filter(|v| *v >= 0)
+            {
+                speed
+                    .with_label_values(&[interface])
+                    .set(value * 1_000_000 / 8);
+            }
+
+            if let Some(value) = has_carrier {
+                carrier.with_label_values(&[interface]).set(value);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Interfaces like loopback, tun or some virtual drivers do not report an
+/// operational state and stay `unknown`, they count as up while they have a
+/// carrier.
+fn is_up(operstate: &str, carrier: Option<i64>) -> bool {
+    operstate == "up" || (operstate == "unknown" && carrier == Some(1))
+}
+
+fn sys_class_net(interface: &str, attribute: &str) -> Option<String> {
+    std::fs::read_to_string(format!("/sys/class/net/{interface}/{attribute}"))
+        .ok()
+        .map(|value| value.trim().to_string())
+}
+
+impl InterfaceStat {
+    fn parse_all(s: &str) -> Result<Vec<Self>, Error> {
+        // The first two lines are the table header.
+        s.lines()
+            .skip(2)
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Self::from_str(line).map_err(Error::msg))
+            .collect()
+    }
+}
+
+impl FromStr for InterfaceStat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (interface, values) = s
+            .split_once(':')
+            .ok_or_else(|| format!("missing interface in net/dev line {s:?}"))?;
+
+        let values = values
+            .split_ascii_whitespace()
+            .map(str::parse)
+            .collect::<Result<Vec<u64>, _>>()
+            .map_err(|err| format!("invalid value in net/dev line {s:?}: {err}"))?;
+
+        if values.len() < 16 {
+            return Err(format!("not enough values in net/dev line {s:?}"));
+        }
+
+        Ok(Self {
+            interface: interface.trim().to_string(),
+            receive_bytes: values[0],
+            receive_packets: values[1],
+            receive_errors: values[2],
+            receive_drop: values[3],
+            receive_fifo: values[4],
+            receive_multicast: values[7],
+            transmit_bytes: values[8],
+            transmit_packets: values[9],
+            transmit_errors: values[10],
+            transmit_drop: values[11],
+            transmit_fifo: values[12],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod parse {
+        use pretty_assertions::assert_eq;
+
+        use crate::probe::system::network::{
+            is_up,
+            InterfaceStat,
+        };
+
+        #[test]
+        fn proc_net_dev() {
+            // cat /proc/net/dev
+            const INPUT: &str = r"Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo: 16213885    2689    0    0    0     0          0         0 16213885    2689    0    0    0     0       0          0
+  eth0: 9876543210 7654321    3   12    1     0          0       421 1234567890 3456789    2    5    4     0       0          0
+wlp3s0:123 4 0 0 0 0 0 0 567 8 0 0 0 0 0 0";
+
+            let expected = vec![
+                InterfaceStat {
+                    interface: "lo".to_string(),
+                    receive_bytes: 16_213_885,
+                    receive_packets: 2689,
+                    transmit_bytes: 16_213_885,
+                    transmit_packets: 2689,
+                    ..InterfaceStat::default()
+                },
+                InterfaceStat {
+                    interface: "eth0".to_string(),
+                    receive_bytes: 9_876_543_210,
+                    receive_packets: 7_654_321,
+                    receive_errors: 3,
+                    receive_drop: 12,
+                    receive_fifo: 1,
+                    receive_multicast: 421,
+                    transmit_bytes: 1_234_567_890,
+                    transmit_packets: 3_456_789,
+                    transmit_errors: 2,
+                    transmit_drop: 5,
+                    transmit_fifo: 4,
+                },
+                InterfaceStat {
+                    interface: "wlp3s0".to_string(),
+                    receive_bytes: 123,
+                    receive_packets: 4,
+                    transmit_bytes: 567,
+                    transmit_packets: 8,
+                    ..InterfaceStat::default()
+                },
+            ];
+
+            let got = InterfaceStat::parse_all(INPUT).unwrap();
+
+            assert_eq!(expected, got);
+        }
+
+        #[test]
+        fn invalid() {
+            assert!("eth0 1 2 3".parse::<InterfaceStat>().is_err());
+            assert!("eth0: 1 2 3".parse::<InterfaceStat>().is_err());
+        }
+
+        #[test]
+        fn up() {
+            let tests = [
+                ("up", Some(1), true),
+                ("up", None, true),
+                ("unknown", Some(1), true),
+                ("unknown", Some(0), false),
+                ("unknown", None, false),
+                ("down", None, false),
+                ("lowerlayerdown", Some(0), false),
+                ("dormant", Some(1), false),
+            ];
+
+            for (operstate, carrier, expected) in tests {
+                assert_eq!(
+                    expected,
+                    is_up(operstate, carrier),
+                    "{operstate} with carrier {carrier:?}"
+                );
+            }
+        }
+    }
+}
@@ -1,38 +1,46 @@
 use anyhow::Error;
-use axum::extract::Query;
 use prometheus::{
     register_int_gauge_with_registry,
-    Encoder,
     Registry,
-    TextEncoder,
 };
-use serde::Deserialize;
+use serde::{
+    Deserialize,
+    Serialize,
+};
 use systemstat::{
     Platform,
     System,
 };
 
-#[derive(Debug, Deserialize)]
+use crate::probe::Probe;
+
+#[derive(Debug, Default, Deserialize)]
 pub(crate) struct Params {}
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct Options {}
+
 #[derive(Debug)]
-pub(super) struct Swap {}
+pub(crate) struct Swap {}
 
-#[allow(clippy::unused_async)]
-pub(crate) async fn handler(Query(_params): Query<Params>) -> Vec<u8> {
-    let registry = Registry::new();
-    Swap::run(&registry).unwrap();
+impl Probe for Swap {
+    const NAME: &'static str = "swap";
+    const LABELS: &'static [&'static str] = &[];
 
-    let mut buffer = vec![];
-    let encoder = TextEncoder::new();
-    let metric_families = registry.gather();
-    encoder.encode(&metric_families, &mut buffer).unwrap();
+    type Options = Options;
+    type Params = Params;
 
-    buffer
+    async fn collect(
+        registry: &Registry,
+        _options: &Options,
+        _params: Params,
+    ) -> Result<(), Error> {
+        Self::run(registry)
+    }
 }
 
 impl Swap {
-    pub(super) fn run(registry: &Registry) -> Result<(), Error> {
+    fn run(registry: &Registry) -> Result<(), Error> {
         let sys = System::new();
         let swap = sys.swap()?;
 
@@ -1,10 +1,340 @@
-use crate::input::Input;
+use std::path::Path;
+
+use anyhow::{
+    bail,
+    Context,
+    Error,
+};
 use serde::{
     Deserialize,
     Serialize,
 };
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+use crate::{
+    agent,
+    input::{
+        Entry,
+        Input,
+    },
+    listen::{
+        self,
+        ListenAddr,
+    },
+    output,
+    probe::Labels,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
 pub(super) struct Settings {
-    pub(super) inputs: Vec<Input>,
+    #[serde(default = "listen::default_addrs")]
+    pub(super) listen: Vec<ListenAddr>,
+
+    #[serde(with = "serde_yaml::with::singleton_map_recursive")]
+    pub(super) inputs: Vec<Entry>,
+
+    /// Added to every metric of every input. The labels of an input take
+    /// precedence.
+    #[serde(default, skip_serializing_if = "Labels::is_empty")]
+    pub(super) global_labels: Labels,
+
+    #[serde(default)]
+    pub(super) agent: agent::Options,
+
+    /// Where the inputs are written to in agent mode. Agent mode is only
+    /// enabled if there is at least one output.
+    #[serde(default, with = "serde_yaml::with::singleton_map_recursive")]
+    pub(super) outputs: Vec<output::Entry>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            listen: listen::default_addrs(),
+            inputs: Vec::default(),
+            global_labels: Labels::default(),
+            agent: agent::Options::default(),
+            outputs: Vec::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub(super) fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("can not read config file {}", path.display()))?;
+
+        let mut settings: Self = serde_yaml::from_str(&content)
+            .with_context(|| format!("can not parse config file {}", path.display()))?;
+
+        settings.validate()?;
+        settings.apply_global_labels();
+
+        Ok(settings)
+    }
+
+    /// Adds the global labels to the labels of every input that does not
+    /// have its own label with the same name.
+    fn apply_global_labels(&mut self) {
+        for entry in &mut self.inputs {
+            for (name, value) in &self.global_labels {
+                entry
+                    .labels
+                    .entry(name.clone())
+                    .or_insert_with(|| value.clone());
+            }
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.listen.is_empty() {
+            bail!("at least one listen address has to be configured")
+        }
+
+        for (index, entry) in self.inputs.iter().enumerate() {
+            let name = entry.input.name();
+
+            if self.inputs[..index]
+                .iter()
+                .any(|other| other.input.name() == name)
+            {
+                bail!("input {name} is configured more than once")
+            }
+
+            if entry.interval.is_some_and(|interval| interval.is_zero()) {
+                bail!("interval of input {name} can not be zero")
+            }
+
+            if let Some(label) = entry.labels.keys().find(|label| !is_label_name(label)) {
+                bail!("label {label:?} of input {name} is not a valid label name")
+            }
+
+            if let Some(label) = entry
+                .labels
+                .keys()
+                .chain(self.global_labels.keys())
+                .find(|label| entry.input.metric_labels().contains(&label.as_str()))
+            {
+                bail!("label {label} collides with a label of the metrics of input {name}")
+            }
+        }
+
+        if let Some(label) = self
+            .global_labels
+            .keys()
+            .find(|label| !is_label_name(label))
+        {
+            bail!("global label {label:?} is not a valid label name")
+        }
+
+        for (index, entry) in self.outputs.iter().enumerate() {
+            let Some(buffer) = &entry.buffer else {
+                continue;
+            };
+
+            if !self
+                .inputs
+                .iter()
+                .any(|input| matches!(input.input, Input::Info(_)))
+            {
+                bail!(
+                    "buffer of output {} needs an Info input to report it",
+                    entry.output.name()
+                )
+            }
+
+            if self.outputs[..index].iter().any(|other| {
+                other
+                    .buffer
+                    .as_ref()
+                    .is_some_and(|other| other.path == buffer.path)
+            }) {
+                bail!(
+                    "buffer {} is used by more than one output",
+                    buffer.path.display()
+                )
+            }
+        }
+
+        if self.agent.interval.is_zero() || self.agent.flush_interval.is_zero() {
+            bail!("agent interval and flush_interval can not be zero")
+        }
+
+        Ok(())
+    }
+}
+
+/// Label names have to match `[a-zA-Z_][a-zA-Z0-9_]*` and names starting
+/// with `__` are reserved by prometheus.
+fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|char| char.is_ascii_alphanumeric() || char == '_')
+        && !name.starts_with("__")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        input::Input,
+        output::Output,
+        probe::format::Format,
+        settings::Settings,
+    };
+
+    #[test]
+    fn parse_inputs() {
+        const INPUT: &str = r"
+inputs:
+  - Info: {}
+  - Cpu:
+      total_cpu: false
+  - Load: {}
+";
+
+        let got: Settings = serde_yaml::from_str(INPUT).unwrap();
+        let names = got
+            .inputs
+            .iter()
+            .map(|entry| entry.input.name())
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec!["info", "cpu", "load"], names);
+        assert!(matches!(&got.inputs[1].input, Input::Cpu(options) if !options.total_cpu));
+    }
+
+    #[test]
+    fn parse_agent() {
+        const INPUT: &str = r"
+inputs:
+  - Cpu:
+      per_cpu: true
+    interval: 30s
+  - Ping:
+      interval: 200ms
+      targets: [127.0.0.1]
+agent:
+  interval: 5s
+  jitter: 1s
+outputs:
+  - Stdout:
+      format: json
+";
+
+        let got: Settings = serde_yaml::from_str(INPUT).unwrap();
+
+        assert_eq!(Some(Duration::from_secs(30)), got.inputs[0].interval);
+        assert!(matches!(&got.inputs[0].input, Input::Cpu(options) if options.per_cpu));
+        assert_eq!(None, got.inputs[1].interval);
+        assert!(
+            matches!(&got.inputs[1].input, Input::Ping(options) if options.interval == Duration::from_millis(200))
+        );
+        assert_eq!(Duration::from_secs(5), got.agent.interval);
+        assert_eq!(Duration::from_secs(10), got.agent.flush_interval);
+        assert!(matches!(
+            &got.outputs[0].output,
+            Output::Stdout(options) if options.format == Format::Json
+        ));
+    }
+
+    #[test]
+    fn duplicate_inputs() {
+        const INPUT: &str = r"
+inputs:
+  - Load: {}
+  - Load: {}
+";
+
+        let got: Settings = serde_yaml::from_str(INPUT).unwrap();
+
+        assert!(got.validate().is_err());
+    }
+
+    #[test]
+    fn global_labels() {
+        const INPUT: &str = r"
+global_labels:
+  datacenter: fra1
+  env: production
+inputs:
+  - Load: {}
+  - Memory: {}
+    labels:
+      env: staging
+";
+
+        let mut got: Settings = serde_yaml::from_str(INPUT).unwrap();
+        got.validate().unwrap();
+        got.apply_global_labels();
+
+        let labels = |index: usize| {
+            got.inputs[index]
+                .labels
+                .iter()
+                .map(|(name, value)| format!("{name}={value}"))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(vec!["datacenter=fra1", "env=production"], labels(0));
+        assert_eq!(vec!["datacenter=fra1", "env=staging"], labels(1));
+    }
+
+    #[test]
+    fn invalid_label() {
+        const INPUT: &str = r"
+inputs:
+  - Load: {}
+    labels:
+      __name__: load
+";
+
+        let got: Settings = serde_yaml::from_str(INPUT).unwrap();
+
+        assert!(got.validate().is_err());
+    }
+
+    #[test]
+    fn label_collision() {
+        const INPUT: &str = r"
+global_labels:
+  device: web1
+inputs:
+  - Load: {}
+  - Disk: {}
+";
+
+        let got: Settings = serde_yaml::from_str(INPUT).unwrap();
+
+        assert_eq!(
+            "label device collides with a label of the metrics of input disk",
+            got.validate().unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn buffer_without_info() {
+        const INPUT: &str = r"
+inputs:
+  - Load: {}
+outputs:
+  - Stdout: {}
+    buffer:
+      path: /var/lib/callipe-rs/buffer
+";
+
+        let got: Settings = serde_yaml::from_str(INPUT).unwrap();
+
+        assert_eq!(
+            "buffer of output stdout needs an Info input to report it",
+            got.validate().unwrap_err().to_string()
+        );
+    }
 }